@@ -8,7 +8,12 @@ fn main() {
     let cargo_dir = var("CARGO_MANIFEST_DIR").unwrap();
     let dist = PathBuf::from(cargo_dir).join("web/dist");
 
-    let webpack = WebAssets::new("ASSETS", dist).build();
+    let webpack = WebAssets::new("ASSETS", dist)
+        .alias("/index.html", "/")
+        .cache_control_hashed("public, max-age=31536000, immutable")
+        .integrity(FilterRule::extension("js"), SriAlgorithm::Sha384)
+        .text(FilterRule::extension("html"))
+        .build();
 
     Codegen::new().pipe(webpack).write();
 }
@@ -38,12 +38,38 @@ mod tests {
     use select::document::Document;
     use select::node::Node;
     use select::predicate::Name;
-    use std::str;
 
     #[test]
     fn index_exists() {
         let index = super::index();
-        assert!(index.len() > 0)
+        assert!(!index.is_empty())
+    }
+
+    /// `/index.html` is an alias of `/` in build.rs, they share the same bytes
+    #[test]
+    fn index_alias_shares_data() {
+        let assets = &super::ASSETS;
+        let alias = assets.iter().find(|a| a.uri() == "/index.html").unwrap();
+        let index = &assets[super::find_index_index(assets)];
+        assert_eq!(alias.data().as_ptr(), index.data().as_ptr());
+    }
+
+    /// webpack hashes the bundle file names, so they can be cached forever
+    #[test]
+    fn bundles_are_immutable() {
+        for asset in super::ASSETS.iter().filter(|a| a.uri().ends_with(".js")) {
+            assert!(asset.cache_control().contains("immutable"));
+        }
+
+        let index = &super::ASSETS[super::find_index_index(&super::ASSETS)];
+        assert_eq!(index.cache_control(), "no-cache");
+    }
+
+    #[test]
+    fn index_normalized_lookup() {
+        // "/index.html" normalizes to "/" before the lookup
+        let found = super::ASSETS_NORMALIZATION.find(&super::ASSETS, "/index.html");
+        assert_eq!(found.map(|a| a.uri()), Some("/"));
     }
 
     /// friendly reminder: this isn't a good test for an actual production
@@ -57,9 +83,8 @@ mod tests {
     /// have a better way of showing this.
     #[test]
     fn index_requires_included_assets() {
-        let index = super::index();
-        let index_s = str::from_utf8(&index).unwrap();
-        let document = Document::from(index_s);
+        let index = &super::ASSETS[super::find_index_index(&super::ASSETS)];
+        let document = Document::from(index.text().unwrap());
 
         // note that this isn't necessarily a good test for a production
         // codebase, this is just for this simple example test.
@@ -73,4 +98,14 @@ mod tests {
         let src = js.attr("src").unwrap();
         assert!(super::ASSETS.iter().any(|asset| asset.uri() == src));
     }
+
+    #[test]
+    fn scripts_have_integrity() {
+        for asset in super::ASSETS.iter().filter(|a| a.uri().ends_with(".js")) {
+            assert!(asset.integrity().unwrap().starts_with("sha384-"));
+            assert_eq!(super::assets_integrity(asset.uri()), asset.integrity());
+        }
+
+        assert_eq!(super::assets_integrity("/"), None);
+    }
 }
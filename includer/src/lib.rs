@@ -1,29 +1,262 @@
+#[cfg(feature = "brotli")]
+extern crate brotli_decompressor;
+#[cfg(feature = "web")]
+extern crate http;
+#[cfg(feature = "pack")]
+extern crate memmap2;
+#[cfg(feature = "deflate")]
+extern crate miniz_oxide;
+#[cfg(feature = "zstd")]
+extern crate ruzstd;
+#[cfg(feature = "pack")]
+extern crate sha2;
+#[cfg(test)]
+extern crate tempfile;
+
+pub mod normalization;
+pub mod overlay;
+#[cfg(feature = "pack")]
+pub mod pack;
 #[cfg(feature = "web")]
 pub mod web;
 
+pub use normalization::{normalize, Normalization, TrailingSlash};
+pub use overlay::{Embedded, Overlaid, Overlay};
+#[cfg(feature = "pack")]
+pub use pack::{PackError, PackFile};
 #[cfg(feature = "web")]
 pub use web::*;
 
+use std::borrow::Cow;
+use std::mem;
+use std::slice;
+use std::sync::OnceLock;
+
 #[derive(Debug)]
 pub struct Asset {
     pub uri: &'static str,
     pub data: &'static [u8],
+
+    /// The data as text, if the asset was validated as UTF-8 at build time.
+    pub text: Option<&'static str>,
+
+    /// How `data` is compressed, if the asset is compressed at rest.
+    pub compression: Option<Compression>,
 }
 
+/// The compression of an asset that is compressed at rest.
+#[derive(Debug)]
+pub struct Compression {
+    pub codec: Codec,
+
+    /// Where the decompressed contents are kept after the first access.
+    pub cache: &'static OnceLock<Vec<u8>>,
+}
+
+/// A codec that assets can be compressed at rest with.
+///
+/// Every codec is behind the cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    /// The zlib format.
+    #[cfg(feature = "deflate")]
+    Deflate,
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// A type that every bit pattern of its size is a valid value of, so that it
+/// can be viewed from embedded bytes.
+///
+/// # Safety
+///
+/// Implementors must have no padding and no invalid bit patterns.
+pub unsafe trait FromBytes {}
+
+macro_rules! from_bytes {
+    ($($ty:ty),*) => {
+        $(unsafe impl FromBytes for $ty {})*
+    };
+}
+
+from_bytes!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: FromBytes, const N: usize> FromBytes for [T; N] {}
+
 impl Asset {
     pub fn uri(&self) -> &'static str {
         self.uri
     }
 
+    /// The embedded data, which is compressed if the asset is compressed at
+    /// rest.  See [`contents`] for the contents of the asset.
+    ///
+    /// [`contents`]: #method.contents
     pub fn data(&self) -> &'static [u8] {
         self.data
     }
+
+    /// The data as text, if the asset was embedded as text.
+    ///
+    /// Text is never compressed at rest.
+    pub fn text(&self) -> Option<&'static str> {
+        self.text
+    }
+
+    /// The contents of the asset.
+    ///
+    /// Assets that are compressed at rest are decompressed on the first
+    /// access, and kept in memory for the rest of the program.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the embedded data can't be decompressed.
+    pub fn contents(&self) -> Cow<'static, [u8]> {
+        match self.compression {
+            Some(ref compression) => {
+                let codec = compression.codec;
+                let contents = compression
+                    .cache
+                    .get_or_init(|| codec.decompress(self.data));
+                Cow::Borrowed(contents)
+            }
+            None => Cow::Borrowed(self.data),
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compression.is_some()
+    }
+
+    /// Views the embedded data as a slice of `T`, without copying it.
+    ///
+    /// Returns `None` if the data isn't aligned for `T`, if its length isn't
+    /// a multiple of the size of `T`, or if the asset is compressed at rest.
+    /// Embedded data is only guaranteed to be aligned by the `align` option of
+    /// the `Assets` pipeline.
+    ///
+    /// ```
+    /// # use includer::Asset;
+    /// static TABLE: [u32; 2] = [1, 2];
+    /// let asset = Asset {
+    ///     uri: "/table.bin",
+    ///     data: unsafe { std::slice::from_raw_parts(TABLE.as_ptr() as *const u8, 8) },
+    ///     text: None,
+    ///     compression: None,
+    /// };
+    /// assert_eq!(asset.as_aligned::<u32>(), Some(&TABLE[..]));
+    /// ```
+    pub fn as_aligned<T: FromBytes>(&self) -> Option<&'static [T]> {
+        let size = mem::size_of::<T>();
+        let ptr = self.data.as_ptr() as *const T;
+        if self.is_compressed() || size == 0 || self.data.len() % size != 0 || !ptr.is_aligned() {
+            return None;
+        }
+
+        // The pointer is aligned, the length is in bounds and `FromBytes`
+        // guarantees that any bytes are a valid `T`.
+        Some(unsafe { slice::from_raw_parts(ptr, self.data.len() / size) })
+    }
+}
+
+impl Codec {
+    #[cfg_attr(
+        not(any(feature = "deflate", feature = "brotli", feature = "zstd")),
+        allow(unreachable_code, unused_variables)
+    )]
+    fn decompress(self, data: &[u8]) -> Vec<u8> {
+        let decompressed: Result<Vec<u8>, String> = match self {
+            #[cfg(feature = "deflate")]
+            Codec::Deflate => {
+                miniz_oxide::inflate::decompress_to_vec_zlib(data).map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "brotli")]
+            Codec::Brotli => {
+                use std::io::Read;
+                let mut out = Vec::new();
+                brotli_decompressor::Decompressor::new(data, 4096)
+                    .read_to_end(&mut out)
+                    .map(|_| out)
+                    .map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                use std::io::Read;
+                let mut out = Vec::new();
+                ruzstd::decoding::StreamingDecoder::new(data)
+                    .map_err(|e| e.to_string())
+                    .and_then(|mut d| d.read_to_end(&mut out).map_err(|e| e.to_string()))
+                    .map(|_| out)
+            }
+        };
+
+        match decompressed {
+            Ok(decompressed) => decompressed,
+            Err(e) => panic!("Unable to decompress asset: {}", e),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn uncompressed_contents() {
+        let asset = Asset {
+            uri: "/hello.txt",
+            data: b"hello",
+            text: Some("hello"),
+            compression: None,
+        };
+        assert_eq!(asset.contents(), Cow::Borrowed(&b"hello"[..]));
+        assert_eq!(asset.text(), Some("hello"));
+        assert!(!asset.is_compressed());
+    }
+
+    #[test]
+    fn aligned_views() {
+        #[repr(C, align(8))]
+        struct Aligned([u8; 9]);
+        static DATA: Aligned = Aligned([1, 0, 0, 0, 2, 0, 0, 0, 3]);
+
+        let asset = |data: &'static [u8]| Asset {
+            uri: "/table.bin",
+            data,
+            text: None,
+            compression: None,
+        };
+        let ints = u32::from_ne_bytes([1, 0, 0, 0]);
+        assert_eq!(asset(&DATA.0[..8]).as_aligned::<u8>().unwrap().len(), 8);
+        assert_eq!(asset(&DATA.0[..8]).as_aligned::<u32>().unwrap()[0], ints);
+        assert!(asset(&DATA.0[..8]).as_aligned::<[u32; 2]>().is_some());
+        assert!(asset(&DATA.0[..9]).as_aligned::<u32>().is_none());
+        assert!(asset(&DATA.0[1..9]).as_aligned::<u32>().is_none());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn deflate_contents() {
+        static CACHE: OnceLock<Vec<u8>> = OnceLock::new();
+        let data = miniz_oxide::deflate::compress_to_vec_zlib(b"hello hello hello", 9);
+        let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+        let asset = Asset {
+            uri: "/hello.txt",
+            data,
+            text: None,
+            compression: Some(Compression {
+                codec: Codec::Deflate,
+                cache: &CACHE,
+            }),
+        };
+        assert_eq!(&asset.contents()[..], b"hello hello hello");
+        assert!(CACHE.get().is_some());
+    }
 }
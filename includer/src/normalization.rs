@@ -0,0 +1,91 @@
+//! Normalization of asset uris and request paths.
+
+/// How [`WebAsset`] uris were normalized by `includer_codegen`.
+///
+/// A `Normalization` is generated alongside every web asset array, named after
+/// it with a `_NORMALIZATION` suffix.  Using it to look up request paths makes
+/// sure they are normalized the exact same way as the generated uris.
+///
+/// [`WebAsset`]: ../struct.WebAsset.html
+#[derive(Debug)]
+pub struct Normalization {
+    /// File names that collapse into the uri of their directory.
+    pub index_files: &'static [&'static str],
+
+    /// Whether directory uris end with a slash.
+    pub trailing_slash: TrailingSlash,
+
+    /// Extensions (without a leading period) stripped from file uris.
+    pub strip_extensions: &'static [&'static str],
+
+    /// Whether uris are lowercased.
+    pub lowercase: bool,
+}
+
+impl Normalization {
+    /// Normalizes a request path.
+    ///
+    /// The path should not contain a query string or fragment.  A directory
+    /// path without its trailing slash can't be told apart from a file path,
+    /// so it's left as is, see [`find`] to look it up.
+    ///
+    /// [`find`]: #method.find
+    pub fn normalize(&self, path: &str) -> String {
+        normalize(
+            path,
+            self.index_files,
+            self.trailing_slash,
+            self.strip_extensions,
+            self.lowercase,
+        )
+    }
+}
+
+/// Whether directory uris end with a slash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrailingSlash {
+    /// `/docs/index.html` is normalized to `/docs/`
+    Always,
+
+    /// `/docs/index.html` is normalized to `/docs`
+    Never,
+}
+
+/// Normalizes a path with the same rules as a [`Normalization`].
+///
+/// This is what `includer_codegen` generates uris with, so that they're
+/// always normalized the same way as the request paths looking them up.
+///
+/// [`Normalization`]: ./struct.Normalization.html
+pub fn normalize<S: AsRef<str>>(
+    path: &str,
+    index_files: &[S],
+    trailing_slash: TrailingSlash,
+    strip_extensions: &[S],
+    lowercase: bool,
+) -> String {
+    let mut uri = if lowercase {
+        path.to_lowercase()
+    } else {
+        path.to_owned()
+    };
+
+    let split = uri.rfind('/').map_or(0, |i| i + 1);
+    let (dir, file) = uri.split_at(split);
+    if index_files.iter().any(|f| f.as_ref() == file) {
+        uri = dir.to_owned();
+    } else if let Some(dot) = file.rfind('.') {
+        let ext = &file[dot + 1..];
+        if dot > 0 && strip_extensions.iter().any(|e| e.as_ref() == ext) {
+            uri = format!("{}{}", dir, &file[..dot]);
+        }
+    }
+
+    match trailing_slash {
+        TrailingSlash::Always => uri,
+        TrailingSlash::Never => match uri.trim_end_matches('/') {
+            "" => "/".to_owned(),
+            trimmed => trimmed.to_owned(),
+        },
+    }
+}
@@ -0,0 +1,494 @@
+//! Overriding embedded assets with files on disk.
+//!
+//! An [`Overlay`] wraps a generated asset array and a directory that files
+//! are looked up in first, so that a single template or stylesheet can be
+//! patched without rebuilding.  Files are named after the uri of the asset
+//! that they override, such as `css/site.css` for `/css/site.css`, and only
+//! assets that are embedded can be overridden.  Web assets whose uris were
+//! normalized are mapped back to their files with their [`Normalization`].
+//! An overriding file is only served as is, without the precompressed and
+//! other variants of the web asset, see [`Overlaid::response`].
+//!
+//! ```no_run
+//! # use includer::{Asset, Overlay};
+//! # static ASSETS: [Asset; 0] = [];
+//! let overlay = Overlay::new(&ASSETS).dir("/etc/service/overlay").cache(true);
+//!
+//! if let Some(asset) = overlay.get("/templates/page.html") {
+//!     let page = asset.contents();
+//! }
+//! ```
+//!
+//! [`Overlay`]: ./struct.Overlay.html
+//! [`Normalization`]: ../struct.Normalization.html
+//! [`Overlaid::response`]: ./struct.Overlaid.html#method.response
+#[cfg(feature = "web")]
+use http::header::{HeaderMap, CACHE_CONTROL, CONTENT_LANGUAGE, CONTENT_TYPE};
+#[cfg(feature = "web")]
+use http::Response;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use Asset;
+use Normalization;
+#[cfg(feature = "web")]
+use WebAsset;
+
+/// An embedded asset that an [`Overlay`] can override.
+///
+/// [`Overlay`]: ./struct.Overlay.html
+pub trait Embedded {
+    /// The uri that the asset is looked up by.
+    fn uri(&self) -> &'static str;
+
+    /// The embedded contents of the asset.
+    fn contents(&self) -> Cow<'static, [u8]>;
+}
+
+impl Embedded for Asset {
+    fn uri(&self) -> &'static str {
+        self.uri
+    }
+
+    fn contents(&self) -> Cow<'static, [u8]> {
+        Asset::contents(self)
+    }
+}
+
+#[cfg(feature = "web")]
+impl Embedded for WebAsset {
+    fn uri(&self) -> &'static str {
+        self.uri
+    }
+
+    fn contents(&self) -> Cow<'static, [u8]> {
+        Cow::Borrowed(self.data)
+    }
+}
+
+/// Embedded assets with an optional directory of files that override them.
+///
+/// Uris are mapped to paths inside of the directory, and any uri with a `..`
+/// or `.` segment, a backslash or a drive prefix is never looked up on disk.
+/// Files that resolve outside of the directory, such as through a symlink,
+/// are ignored too.  Files that are missing or can't be read fall back to the
+/// embedded asset.
+#[derive(Debug)]
+pub struct Overlay<'a, A: 'a> {
+    assets: &'a [A],
+    dir: Option<PathBuf>,
+    index_files: Vec<String>,
+    strip_extensions: Vec<String>,
+    cache: Option<Cache>,
+}
+
+/// The files read for every uri, or `None` for uris without a file.
+type Cache = Mutex<HashMap<String, Option<Arc<[u8]>>>>;
+
+/// An embedded asset, along with the contents of the file overriding it.
+#[derive(Debug)]
+pub struct Overlaid<'a, A: 'a> {
+    asset: &'a A,
+    file: Option<Arc<[u8]>>,
+}
+
+impl<'a, A: Embedded> Overlay<'a, A> {
+    /// Creates an `Overlay` without a directory, which only has the embedded
+    /// assets.
+    pub fn new(assets: &'a [A]) -> Self {
+        Overlay {
+            assets,
+            dir: None,
+            index_files: vec!["index.html".to_owned()],
+            strip_extensions: Vec::new(),
+            cache: None,
+        }
+    }
+
+    /// Sets the directory that overriding files are looked up in.
+    pub fn dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the file name that overrides the uri of its directory.
+    ///
+    /// Defaults to `index.html`.
+    pub fn index_file<S: Into<String>>(mut self, name: S) -> Self {
+        self.index_files = vec![name.into()];
+        self
+    }
+
+    /// Maps uris to files with the index files and stripped extensions that
+    /// the uris were normalized with, such as `about.html` for `/about`.
+    ///
+    /// Lowercased uris are still looked up by their lowercase name.
+    pub fn normalization(mut self, normalization: &Normalization) -> Self {
+        let owned = |names: &[&str]| names.iter().map(|&name| name.to_owned()).collect();
+        self.index_files = owned(normalization.index_files);
+        self.strip_extensions = owned(normalization.strip_extensions);
+        self
+    }
+
+    /// Sets whether overriding files are kept in memory after they're read.
+    ///
+    /// Missing files are remembered as well, so the directory is only looked
+    /// at once per uri until [`clear_cache`] is called.  Defaults to `false`,
+    /// reading the file on every lookup.
+    ///
+    /// [`clear_cache`]: #method.clear_cache
+    pub fn cache(mut self, cache: bool) -> Self {
+        self.cache = if cache {
+            Some(Mutex::new(HashMap::new()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Forgets the cached files, so that they're read again.
+    pub fn clear_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            cache.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    /// The embedded assets.
+    pub fn assets(&self) -> &'a [A] {
+        self.assets
+    }
+
+    /// Finds the embedded asset with the uri, overridden by its file if the
+    /// directory has one.
+    pub fn get(&self, uri: &str) -> Option<Overlaid<'a, A>> {
+        let asset = self.assets.iter().find(|asset| asset.uri() == uri)?;
+        Some(self.overlay(asset))
+    }
+
+    /// Overrides an asset that was already found, such as by a `Resolver`,
+    /// with its file if the directory has one.
+    pub fn overlay(&self, asset: &'a A) -> Overlaid<'a, A> {
+        let uri = asset.uri();
+        let cache = match self.cache {
+            Some(ref cache) => cache,
+            None => {
+                let file = self.read(uri);
+                return Overlaid { asset, file };
+            }
+        };
+
+        let cached = cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(uri)
+            .cloned();
+        let file = match cached {
+            Some(file) => file,
+            None => {
+                // the file is read without holding the lock, so that other
+                // lookups don't wait on the disk
+                let file = self.read(uri);
+                let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.entry(uri.to_owned()).or_insert(file).clone()
+            }
+        };
+
+        Overlaid { asset, file }
+    }
+
+    /// Reads the file overriding the uri, if there's one inside of the
+    /// directory.
+    ///
+    /// The uri is looked up as a file, then with every stripped extension,
+    /// then as a directory with every index file.
+    fn read(&self, uri: &str) -> Option<Arc<[u8]>> {
+        let dir = self.dir.as_ref()?;
+        let path = relative_path(uri)?;
+
+        let mut paths = Vec::new();
+        if !uri.is_empty() && !uri.ends_with('/') {
+            paths.push(path.clone());
+            for ext in &self.strip_extensions {
+                let mut file = path.clone().into_os_string();
+                file.push(".");
+                file.push(ext);
+                paths.push(file.into());
+            }
+        }
+        for index_file in &self.index_files {
+            paths.extend(relative_path(index_file).map(|index| path.join(index)));
+        }
+
+        let dir = fs::canonicalize(dir).ok()?;
+        paths.into_iter().find_map(|path| {
+            let path = fs::canonicalize(dir.join(path)).ok()?;
+            if !path.starts_with(&dir) || !path.is_file() {
+                return None;
+            }
+
+            fs::read(path).ok().map(Arc::from)
+        })
+    }
+}
+
+impl<'a, A: Embedded> Overlaid<'a, A> {
+    /// The embedded asset.
+    pub fn asset(&self) -> &'a A {
+        self.asset
+    }
+
+    /// Whether the asset is overridden by a file.
+    ///
+    /// The precompressed and other variants of an overridden web asset are
+    /// still of the embedded asset, so they shouldn't be served instead of
+    /// its contents, which [`response`] takes care of.
+    ///
+    /// [`response`]: #method.response
+    pub fn is_overridden(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// The contents of the file overriding the asset, or else the contents of
+    /// the embedded asset.
+    pub fn contents(&self) -> Cow<'_, [u8]> {
+        match self.file {
+            Some(ref file) => Cow::Borrowed(file),
+            None => self.asset.contents(),
+        }
+    }
+}
+
+#[cfg(feature = "web")]
+impl<'a> Overlaid<'a, WebAsset> {
+    /// Builds a response serving the asset for a request with the headers.
+    ///
+    /// An asset that isn't overridden is served with `WebAsset::response`.
+    /// An overriding file is served as is, identity-encoded, with the
+    /// `Cache-Control`, `Content-Type`, `Content-Language` and custom headers
+    /// of the asset, since the variants of the asset are of the embedded data.
+    pub fn response(&self, request_headers: &HeaderMap) -> Response<Cow<'_, [u8]>> {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return self.asset.response(request_headers).map(Cow::Borrowed),
+        };
+
+        let mut response = Response::builder();
+        response.header(CACHE_CONTROL, self.asset.cache_control);
+        response.header(CONTENT_TYPE, self.asset.media_type);
+        if let Some(language) = self.asset.language {
+            response.header(CONTENT_LANGUAGE, language);
+        }
+
+        for (name, value) in self.asset.headers {
+            response.header(*name, *value);
+        }
+
+        response
+            .body(Cow::Borrowed(&file[..]))
+            .expect("Generated asset headers should be valid")
+    }
+}
+
+/// Maps a uri to a relative path, if it only has plain segments.
+fn relative_path(uri: &str) -> Option<PathBuf> {
+    if uri.contains('\\') || uri.contains('\0') {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+    for segment in uri.split('/').filter(|segment| !segment.is_empty()) {
+        match Path::new(segment).components().next() {
+            Some(Component::Normal(name)) if name == segment => path.push(name),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TrailingSlash;
+
+    const ASSETS: [Asset; 3] = [
+        Asset {
+            uri: "/",
+            data: b"embedded index",
+            text: None,
+            compression: None,
+        },
+        Asset {
+            uri: "/css/site.css",
+            data: b"embedded css",
+            text: None,
+            compression: None,
+        },
+        Asset {
+            uri: "/../secret.txt",
+            data: b"embedded secret",
+            text: None,
+            compression: None,
+        },
+    ];
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path("/css/site.css"), Some("css/site.css".into()));
+        assert_eq!(relative_path("//a//b/"), Some("a/b".into()));
+        assert_eq!(relative_path("/"), Some(PathBuf::new()));
+        assert_eq!(relative_path("/../etc/passwd"), None);
+        assert_eq!(relative_path("/a/./b"), None);
+        assert_eq!(relative_path("/a\\..\\b"), None);
+        assert_eq!(relative_path("/a\0b"), None);
+    }
+
+    #[test]
+    fn overrides_embedded_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (root, dir) = (tmp.path(), tmp.path().join("overlay"));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(dir.join("index.html"), "patched index").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+
+        let embedded = Overlay::new(&ASSETS);
+        assert_eq!(
+            &embedded.get("/").unwrap().contents()[..],
+            b"embedded index"
+        );
+
+        let overlay = Overlay::new(&ASSETS).dir(&dir);
+        let index = overlay.get("/").unwrap();
+        assert!(index.is_overridden());
+        assert_eq!(&index.contents()[..], b"patched index");
+        assert_eq!(index.asset().uri, "/");
+
+        let css = overlay.get("/css/site.css").unwrap();
+        assert!(!css.is_overridden());
+        assert_eq!(&css.contents()[..], b"embedded css");
+        assert!(overlay.get("/css/missing.css").is_none());
+
+        // never read from outside of the directory
+        let secret = overlay.get("/../secret.txt").unwrap();
+        assert_eq!(&secret.contents()[..], b"embedded secret");
+
+        let cached = Overlay::new(&ASSETS).dir(&dir).cache(true);
+        assert!(!cached.get("/css/site.css").unwrap().is_overridden());
+        fs::write(dir.join("css/site.css"), "patched css").unwrap();
+        assert!(overlay.get("/css/site.css").unwrap().is_overridden());
+        assert!(!cached.get("/css/site.css").unwrap().is_overridden());
+        cached.clear_cache();
+        let css = cached.get("/css/site.css").unwrap();
+        assert_eq!(&css.contents()[..], b"patched css");
+    }
+
+    #[test]
+    fn maps_normalized_uris() {
+        const NORMALIZED: [Asset; 3] = [
+            Asset {
+                uri: "/about",
+                data: b"embedded about",
+                text: None,
+                compression: None,
+            },
+            Asset {
+                uri: "/docs",
+                data: b"embedded docs",
+                text: None,
+                compression: None,
+            },
+            Asset {
+                uri: "/blog",
+                data: b"embedded blog",
+                text: None,
+                compression: None,
+            },
+        ];
+        const NORMALIZATION: Normalization = Normalization {
+            index_files: &["index.html", "default.htm"],
+            trailing_slash: TrailingSlash::Never,
+            strip_extensions: &["html"],
+            lowercase: false,
+        };
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::create_dir_all(dir.join("blog")).unwrap();
+        fs::write(dir.join("about.html"), "patched about").unwrap();
+        fs::write(dir.join("docs/default.htm"), "patched docs").unwrap();
+
+        let overlay = Overlay::new(&NORMALIZED).dir(dir);
+        assert!(!overlay.get("/about").unwrap().is_overridden());
+
+        let overlay = overlay.normalization(&NORMALIZATION);
+        let about = overlay.get("/about").unwrap();
+        assert_eq!(&about.contents()[..], b"patched about");
+        let docs = overlay.get("/docs").unwrap();
+        assert_eq!(&docs.contents()[..], b"patched docs");
+
+        // a directory without an index file isn't a file
+        let blog = overlay.get("/blog").unwrap();
+        assert_eq!(&blog.contents()[..], b"embedded blog");
+    }
+
+    #[cfg(feature = "web")]
+    #[test]
+    fn overridden_responses() {
+        use http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, VARY};
+        use Variant;
+
+        const WEB_ASSETS: [WebAsset; 1] = [WebAsset {
+            uri: "/css/site.css",
+            data: b"embedded css",
+            text: None,
+            media_type: "text/css",
+            cache_control: "no-cache",
+            headers: &[("x-frame-options", "DENY")],
+            integrity: None,
+            language: None,
+            variants: &[Variant {
+                media_type: None,
+                encoding: Some("br"),
+                language: None,
+                data: b"compressed css",
+            }],
+        }];
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(ACCEPT_ENCODING, "br".parse().unwrap());
+
+        let overlay = Overlay::new(&WEB_ASSETS).dir(dir);
+        let css = overlay.get("/css/site.css").unwrap();
+        let response = css.response(&request_headers);
+        assert_eq!(&response.body()[..], b"compressed css");
+        assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(dir.join("css/site.css"), "patched css").unwrap();
+        let css = overlay.get("/css/site.css").unwrap();
+        let response = css.response(&request_headers);
+        assert_eq!(&response.body()[..], b"patched css");
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/css");
+        assert_eq!(response.headers()[CACHE_CONTROL], "no-cache");
+        assert_eq!(response.headers()["x-frame-options"], "DENY");
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert!(response.headers().get(VARY).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignores_symlinks_out_of_the_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (root, dir) = (tmp.path(), tmp.path().join("overlay"));
+        fs::create_dir_all(dir.join("css")).unwrap();
+        fs::write(root.join("outside.css"), "outside").unwrap();
+        std::os::unix::fs::symlink(root.join("outside.css"), dir.join("css/site.css")).unwrap();
+
+        let overlay = Overlay::new(&ASSETS).dir(&dir);
+        assert!(!overlay.get("/css/site.css").unwrap().is_overridden());
+    }
+}
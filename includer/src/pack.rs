@@ -0,0 +1,391 @@
+//! Loading of asset data from a pack file next to the executable.
+//!
+//! `includer_codegen` can write the data of its pipelines into a pack file
+//! instead of embedding it, so that updating the assets doesn't mean shipping
+//! a new executable.  The generated code refers to a `static` [`PackFile`]
+//! that is loaded on the first access of an asset.
+//!
+//! The pack file is written into `OUT_DIR` by the build script, and it needs
+//! to be copied next to the executable to ship it.  To update the pack of a
+//! running program, write the new pack into another file and rename it over
+//! the old one, which never modifies the file that is already loaded.
+//!
+//! A pack file starts with a header, followed by the data at the offset that
+//! the header gives:
+//!
+//! | offset | size | contents                                        |
+//! |--------|------|-------------------------------------------------|
+//! | 0      | 8    | the magic bytes `INCLPACK`                      |
+//! | 8      | 4    | the format version, little endian               |
+//! | 12     | 4    | the offset of the data, little endian           |
+//! | 16     | 8    | the length of the data, little endian           |
+//! | 24     | 32   | the SHA-256 digest of the data                  |
+//!
+//! [`PackFile`]: ./struct.PackFile.html
+use memmap2::Mmap;
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// The magic bytes that every pack file starts with.
+pub const PACK_MAGIC: &[u8; 8] = b"INCLPACK";
+
+/// The version of the pack file format.
+pub const PACK_VERSION: u32 = 1;
+
+const HEADER_LEN: usize = 56;
+
+/// A pack file that the data of assets is loaded from.
+///
+/// The file is looked up next to the executable, then at the path it was
+/// written to by the build so that tests and examples find the pack of their
+/// crate.  It's read into memory at the alignment of its data, or memory
+/// mapped with [`mapped`].  Its header is validated against the digest of the
+/// data that the program was built with, so that a pack from another build is
+/// never used, and the data itself is hashed too with [`verified`].
+///
+/// [`mapped`]: #method.mapped
+/// [`verified`]: #method.verified
+#[derive(Debug)]
+pub struct PackFile {
+    name: &'static str,
+    build_path: &'static str,
+    digest: [u8; 32],
+    len: usize,
+    fallback: Option<&'static [u8]>,
+    mapped: bool,
+    verified: bool,
+    data: OnceLock<Result<Data, PackError>>,
+}
+
+#[derive(Debug)]
+enum Data {
+    Mapped(Mmap, usize),
+    Read(Vec<u8>, usize),
+    Embedded(&'static [u8]),
+}
+
+/// Why a pack file couldn't be loaded.
+#[derive(Debug)]
+pub enum PackError {
+    /// The file couldn't be found or read.
+    Io(PathBuf, io::Error),
+    /// The file isn't a pack file, or it's truncated.
+    Invalid(PathBuf),
+    /// The file is in another version of the format.
+    Version(PathBuf, u32),
+    /// The data isn't the data of this build.
+    Mismatch(PathBuf),
+}
+
+impl PackFile {
+    /// Creates a pack file with the file name, the path that the build wrote
+    /// it to, the digest and the length of its data, and the embedded copy of
+    /// the data to use if it can't be loaded.
+    pub const fn new(
+        name: &'static str,
+        build_path: &'static str,
+        digest: [u8; 32],
+        len: usize,
+        fallback: Option<&'static [u8]>,
+    ) -> Self {
+        PackFile {
+            name,
+            build_path,
+            digest,
+            len,
+            fallback,
+            mapped: false,
+            verified: false,
+            data: OnceLock::new(),
+        }
+    }
+
+    /// Sets whether the file is memory mapped instead of read into memory.
+    ///
+    /// A mapped file must never be modified while the program runs, as its
+    /// data would change underneath the assets.  Only replace it by renaming
+    /// another file over it.  Mapped data is only aligned up to the page
+    /// size.  Defaults to `false`.
+    pub const fn mapped(mut self, mapped: bool) -> Self {
+        self.mapped = mapped;
+        self
+    }
+
+    /// Sets whether the data is hashed and compared with the digest of the
+    /// build when it's loaded, instead of only the digest in the header.
+    ///
+    /// This catches a pack that was corrupted after it was written, but it
+    /// reads all of the data up front, even when it's mapped.  Defaults to
+    /// `false`.
+    pub const fn verified(mut self, verified: bool) -> Self {
+        self.verified = verified;
+        self
+    }
+
+    /// The file name of the pack.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Loads the pack, if it's not already loaded, and returns its data.
+    ///
+    /// Without a fallback, an error is returned if the pack can't be loaded.
+    /// Call this at startup to report a missing pack before any asset is
+    /// accessed.
+    pub fn load(&self) -> Result<&[u8], &PackError> {
+        let data = self.data.get_or_init(|| match self.open() {
+            Err(_) if self.fallback.is_some() => Ok(Data::Embedded(self.fallback.unwrap())),
+            result => result,
+        });
+
+        match data {
+            Ok(Data::Mapped(mmap, offset)) => Ok(&mmap[*offset..*offset + self.len]),
+            Ok(Data::Read(data, offset)) => Ok(&data[*offset..*offset + self.len]),
+            Ok(Data::Embedded(data)) => Ok(data),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the data is the embedded fallback instead of the file.
+    pub fn is_fallback(&self) -> bool {
+        matches!(self.data.get(), Some(Ok(Data::Embedded(_))))
+    }
+
+    /// Returns the data of the pack at the offset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pack can't be loaded.
+    pub fn slice(&'static self, offset: usize, len: usize) -> &'static [u8] {
+        match self.load() {
+            Ok(data) => &data[offset..offset + len],
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn open(&self) -> Result<Data, PackError> {
+        let path = self.path();
+        let data = if self.mapped {
+            let file = File::open(&path).map_err(|e| PackError::Io(path.clone(), e))?;
+
+            // Safety: the file is only read and it must not be modified while
+            // the program runs, see `mapped`.
+            let mmap = unsafe { Mmap::map(&file) }.map_err(|e| PackError::Io(path.clone(), e))?;
+            let offset = self.check(&mmap, &path)?;
+            Data::Mapped(mmap, offset)
+        } else {
+            self.read(&path)?
+        };
+
+        Ok(data)
+    }
+
+    /// Reads the pack into memory, with its data at the alignment of its
+    /// offset.
+    fn read(&self, path: &Path) -> Result<Data, PackError> {
+        let io_error = |e| PackError::Io(path.to_path_buf(), e);
+        let mut file = File::open(path).map_err(io_error)?;
+        let len = file.metadata().map_err(io_error)?.len() as usize;
+        let mut header = [0; HEADER_LEN];
+        if len < HEADER_LEN {
+            return Err(PackError::Invalid(path.to_path_buf()));
+        }
+        file.read_exact(&mut header).map_err(io_error)?;
+
+        // the offset of the data is a multiple of its alignment, so aligning
+        // the start of the pack to it aligns the data
+        let offset = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if (offset as usize) < HEADER_LEN || offset as usize > len {
+            return Err(PackError::Invalid(path.to_path_buf()));
+        }
+        let alignment = 1 << offset.trailing_zeros().min(29);
+        let mut buffer = vec![0; len + alignment - 1];
+        let start = (alignment - buffer.as_ptr() as usize % alignment) % alignment;
+        let pack = &mut buffer[start..start + len];
+        pack[..HEADER_LEN].copy_from_slice(&header);
+        file.read_exact(&mut pack[HEADER_LEN..]).map_err(io_error)?;
+
+        let offset = self.check(pack, path)?;
+        Ok(Data::Read(buffer, start + offset))
+    }
+
+    /// Checks that the pack is the pack of this build, returning the offset
+    /// of its data.
+    fn check(&self, pack: &[u8], path: &Path) -> Result<usize, PackError> {
+        let offset = validate(pack, path)?;
+        let len = u64::from_le_bytes(pack[16..24].try_into().unwrap());
+        if len != self.len as u64
+            || pack[24..56] != self.digest
+            || (self.verified
+                && Sha256::digest(&pack[offset..offset + self.len])[..] != self.digest)
+        {
+            return Err(PackError::Mismatch(path.to_path_buf()));
+        }
+
+        Ok(offset)
+    }
+
+    /// Returns the path of the pack next to the executable, or the path the
+    /// build wrote it to if it's only there.
+    fn path(&self) -> PathBuf {
+        let exe = env::current_exe().unwrap_or_default();
+        let dir = exe.parent().unwrap_or_else(|| Path::new("."));
+        let path = dir.join(self.name);
+        let build_path = Path::new(self.build_path);
+        if !path.exists() && build_path.exists() {
+            build_path.to_path_buf()
+        } else {
+            path
+        }
+    }
+}
+
+/// Validates the header of the pack, returning the offset of its data.
+fn validate(pack: &[u8], path: &Path) -> Result<usize, PackError> {
+    if pack.len() < HEADER_LEN || &pack[..8] != PACK_MAGIC {
+        return Err(PackError::Invalid(path.to_path_buf()));
+    }
+
+    let version = u32::from_le_bytes(pack[8..12].try_into().unwrap());
+    if version != PACK_VERSION {
+        return Err(PackError::Version(path.to_path_buf(), version));
+    }
+
+    let offset = u32::from_le_bytes(pack[12..16].try_into().unwrap()) as usize;
+    let len = u64::from_le_bytes(pack[16..24].try_into().unwrap());
+    if offset < HEADER_LEN || (pack.len() - offset.min(pack.len())) as u64 != len {
+        return Err(PackError::Invalid(path.to_path_buf()));
+    }
+
+    Ok(offset)
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackError::Io(path, e) => write!(f, "Unable to read `{}`: {}", path.display(), e),
+            PackError::Invalid(path) => write!(f, "`{}` is not a pack file", path.display()),
+            PackError::Version(path, version) => write!(
+                f,
+                "`{}` is version {} of the pack format, expected {}",
+                path.display(),
+                version,
+                PACK_VERSION
+            ),
+            PackError::Mismatch(path) => {
+                write!(f, "`{}` is not the pack of this build", path.display())
+            }
+        }
+    }
+}
+
+impl Error for PackError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn pack(data: &[u8]) -> Vec<u8> {
+        aligned_pack(data, 64)
+    }
+
+    fn aligned_pack(data: &[u8], offset: usize) -> Vec<u8> {
+        let mut pack = PACK_MAGIC.to_vec();
+        pack.extend(&PACK_VERSION.to_le_bytes());
+        pack.extend(&(offset as u32).to_le_bytes());
+        pack.extend(&(data.len() as u64).to_le_bytes());
+        pack.extend(Sha256::digest(data));
+        pack.resize(offset, 0);
+        pack.extend(data);
+        pack
+    }
+
+    fn leak(path: PathBuf) -> &'static str {
+        Box::leak(path.to_str().unwrap().into())
+    }
+
+    #[test]
+    fn headers() {
+        let path = Path::new("assets.pack");
+        assert_eq!(validate(&pack(b"hello"), path).unwrap(), 64);
+        assert!(validate(b"INCLPACK", path).is_err());
+        assert!(validate(&pack(b"hello")[..68], path).is_err());
+
+        let mut other = pack(b"hello");
+        other[8] = 2;
+        match validate(&other, path) {
+            Err(PackError::Version(_, 2)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn loads_from_build_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("includer-test.pack");
+        fs::write(&path, pack(b"world")).unwrap();
+        let build_path = leak(path);
+
+        // the digest of the file isn't the digest of the build
+        let fallback = PackFile::new("includer-test.pack", build_path, [0; 32], 5, Some(b"hello"));
+        assert_eq!(fallback.load().unwrap(), b"hello");
+        assert!(fallback.is_fallback());
+
+        let digest: [u8; 32] = Sha256::digest(b"world").into();
+        let file = PackFile::new("includer-test.pack", build_path, digest, 5, None);
+        assert_eq!(file.load().unwrap(), b"world");
+        assert!(!file.is_fallback());
+
+        let mapped = PackFile::new("includer-test.pack", build_path, digest, 5, None).mapped(true);
+        assert_eq!(mapped.load().unwrap(), b"world");
+
+        let missing = tmp.path().join("includer-missing.pack");
+        let missing = PackFile::new("includer-missing.pack", leak(missing), digest, 5, None);
+        assert!(matches!(missing.load(), Err(PackError::Io(..))));
+    }
+
+    #[test]
+    fn aligned_reads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let digest: [u8; 32] = Sha256::digest(b"world").into();
+        for &alignment in &[64, 4096] {
+            let path = tmp.path().join(format!("includer-{}.pack", alignment));
+            fs::write(&path, aligned_pack(b"world", alignment)).unwrap();
+
+            let file = PackFile::new("includer-test.pack", leak(path), digest, 5, None);
+            let data = file.load().unwrap();
+            assert_eq!(data, b"world");
+            assert_eq!(data.as_ptr() as usize % alignment, 0);
+        }
+    }
+
+    #[test]
+    fn verified_data() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("includer-test.pack");
+        let mut corrupted = pack(b"world");
+        corrupted[64] = b'W';
+        fs::write(&path, corrupted).unwrap();
+        let build_path = leak(path);
+
+        // only the header is compared with the digest of the build by default
+        let digest: [u8; 32] = Sha256::digest(b"world").into();
+        let file = PackFile::new("includer-test.pack", build_path, digest, 5, None);
+        assert_eq!(file.load().unwrap(), b"World");
+
+        for &mapped in &[false, true] {
+            let verified = PackFile::new("includer-test.pack", build_path, digest, 5, None)
+                .mapped(mapped)
+                .verified(true);
+            assert!(matches!(verified.load(), Err(PackError::Mismatch(..))));
+        }
+    }
+}
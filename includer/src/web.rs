@@ -1,9 +1,51 @@
+use http::header::{HeaderName, HeaderValue};
+use http::header::{ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE, CACHE_CONTROL, CONTENT_ENCODING};
+use http::header::{CONTENT_LANGUAGE, CONTENT_TYPE, LOCATION, VARY};
+use http::{HeaderMap, Response, StatusCode};
+use normalization::{Normalization, TrailingSlash};
+
 #[derive(Debug)]
 pub struct WebAsset {
     pub uri: &'static str,
     pub data: &'static [u8],
-    pub data_gz: Option<&'static [u8]>,
-    pub data_br: Option<&'static [u8]>,
+
+    /// The data as text, if the asset was validated as UTF-8 at build time.
+    pub text: Option<&'static str>,
+
+    /// The media type of the data, such as `"text/css"`.
+    pub media_type: &'static str,
+
+    pub cache_control: &'static str,
+    pub headers: &'static [(&'static str, &'static str)],
+    pub integrity: Option<&'static str>,
+
+    /// The language of the asset itself, if it was configured.
+    pub language: Option<&'static str>,
+
+    /// Alternate representations of the asset, see [`negotiate`].
+    ///
+    /// [`negotiate`]: ./fn.negotiate.html
+    pub variants: &'static [Variant],
+}
+
+/// An alternate representation of a [`WebAsset`].
+///
+/// Every property that is `None` is the same as the asset itself, such as a
+/// gzip variant that only has an `encoding`.
+///
+/// [`WebAsset`]: ./struct.WebAsset.html
+#[derive(Debug)]
+pub struct Variant {
+    /// The media type, such as `"image/webp"`.
+    pub media_type: Option<&'static str>,
+
+    /// The content coding, such as `"br"`.
+    pub encoding: Option<&'static str>,
+
+    /// The language tag, such as `"fr"`.
+    pub language: Option<&'static str>,
+
+    pub data: &'static [u8],
 }
 
 impl WebAsset {
@@ -15,11 +57,1106 @@ impl WebAsset {
         self.data
     }
 
+    /// The data as text, if the asset was embedded as text.
+    pub fn text(&self) -> Option<&'static str> {
+        self.text
+    }
+
+    /// The gzip variant of the asset, if there is one.
     pub fn data_gz(&self) -> Option<&'static [u8]> {
-        self.data_gz
+        self.encoding("gzip")
     }
 
+    /// The brotli variant of the asset, if there is one.
     pub fn data_br(&self) -> Option<&'static [u8]> {
-        self.data_br
+        self.encoding("br")
+    }
+
+    /// The zstd variant of the asset, if there is one.
+    pub fn data_zstd(&self) -> Option<&'static [u8]> {
+        self.encoding("zstd")
+    }
+
+    /// The deflate variant of the asset, if there is one.
+    pub fn data_deflate(&self) -> Option<&'static [u8]> {
+        self.encoding("deflate")
+    }
+
+    /// The WebP variant of an image, if there is one.
+    pub fn data_webp(&self) -> Option<&'static [u8]> {
+        self.variants
+            .iter()
+            .find(|v| v.media_type == Some("image/webp") && v.encoding.is_none())
+            .map(|v| v.data)
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        self.media_type
+    }
+
+    pub fn cache_control(&self) -> &'static str {
+        self.cache_control
+    }
+
+    pub fn headers(&self) -> &'static [(&'static str, &'static str)] {
+        self.headers
+    }
+
+    /// The Subresource Integrity digest, such as `"sha384-..."`, if computed.
+    pub fn integrity(&self) -> Option<&'static str> {
+        self.integrity
+    }
+
+    pub fn language(&self) -> Option<&'static str> {
+        self.language
+    }
+
+    pub fn variants(&self) -> &'static [Variant] {
+        self.variants
+    }
+
+    fn encoding(&self, coding: &str) -> Option<&'static [u8]> {
+        self.variants
+            .iter()
+            .find(|v| v.encoding == Some(coding) && v.media_type.is_none() && v.language.is_none())
+            .map(|v| v.data)
+    }
+
+    /// Picks the data to send for an `Accept-Encoding` header value.
+    ///
+    /// Only the variants that differ in their encoding are considered, see
+    /// [`negotiate`] for all of them.  Returns the content encoding of the
+    /// data if it isn't the identity.
+    ///
+    /// [`negotiate`]: ./fn.negotiate.html
+    pub fn encoded(&self, accept_encoding: &str) -> (Option<&'static str>, &'static [u8]) {
+        let variants = self
+            .variants
+            .iter()
+            .filter(|v| v.media_type.is_none() && v.language.is_none());
+        match best(self, variants, "", accept_encoding, "") {
+            Some(variant) => (variant.encoding, variant.data),
+            None => (None, self.data),
+        }
+    }
+
+    /// Picks the image data to send for an `Accept` header value.
+    ///
+    /// Only the variants that differ in their media type are considered, see
+    /// [`negotiate`] for all of them.  Returns the media type of the data if
+    /// it's a variant.
+    ///
+    /// [`negotiate`]: ./fn.negotiate.html
+    pub fn image(&self, accept: &str) -> (Option<&'static str>, &'static [u8]) {
+        let variants = self
+            .variants
+            .iter()
+            .filter(|v| v.encoding.is_none() && v.language.is_none());
+        match best(self, variants, accept, "", "") {
+            Some(variant) => (variant.media_type, variant.data),
+            None => (None, self.data),
+        }
+    }
+
+    /// Builds a response serving the asset for a request with the headers.
+    ///
+    /// The body is picked with [`negotiate`], and the `Cache-Control`,
+    /// `Content-Type`, `Content-Encoding`, `Content-Language` and `Vary`
+    /// headers are set accordingly.  The custom [`headers`] of the asset are
+    /// added afterwards.
+    ///
+    /// [`negotiate`]: ./fn.negotiate.html
+    /// [`headers`]: #structfield.headers
+    pub fn response(&self, request_headers: &HeaderMap) -> Response<&'static [u8]> {
+        let variant = negotiate(
+            self,
+            &joined(request_headers, ACCEPT),
+            &joined(request_headers, ACCEPT_ENCODING),
+            &joined(request_headers, ACCEPT_LANGUAGE),
+        );
+
+        let mut response = Response::builder();
+        response.header(CACHE_CONTROL, self.cache_control);
+
+        let media_type = variant.and_then(|v| v.media_type);
+        response.header(CONTENT_TYPE, media_type.unwrap_or(self.media_type));
+
+        if let Some(encoding) = variant.and_then(|v| v.encoding) {
+            response.header(CONTENT_ENCODING, encoding);
+        }
+
+        if let Some(language) = variant.and_then(|v| v.language).or(self.language) {
+            response.header(CONTENT_LANGUAGE, language);
+        }
+
+        let mut vary = Vec::new();
+        if self.variants.iter().any(|v| v.media_type.is_some()) {
+            vary.push("Accept");
+        }
+
+        if self.variants.iter().any(|v| v.encoding.is_some()) {
+            vary.push("Accept-Encoding");
+        }
+
+        if self.variants.iter().any(|v| v.language.is_some()) {
+            vary.push("Accept-Language");
+        }
+
+        if !vary.is_empty() {
+            response.header(VARY, vary.join(", "));
+        }
+
+        for (name, value) in self.headers {
+            response.header(*name, *value);
+        }
+
+        let data = variant.map_or(self.data, |v| v.data);
+        response
+            .body(data)
+            .expect("Generated asset headers should be valid")
+    }
+}
+
+/// Picks the best variant of the asset for the `Accept`, `Accept-Encoding`
+/// and `Accept-Language` header values of a request.
+///
+/// Returns `None` when the asset itself is the best representation.
+///
+/// The language is the most important, then the media type and then the
+/// encoding.  Variants with a media type or encoding the client doesn't
+/// accept are never picked, and media types need to be accepted explicitly
+/// since clients send `*/*` regardless of what they support.  Within the
+/// acceptable variants, every variant is preferred over the asset itself and
+/// earlier variants are preferred over later ones with the same quality.
+///
+/// An asset without a language is only picked over a language variant when
+/// the client accepts none of the variant languages, so set the language of
+/// the asset when generating it if it has language variants.
+///
+/// ```
+/// # use includer::{negotiate, Variant, WebAsset};
+/// const VARIANTS: &[Variant] = &[
+///     Variant { media_type: None, encoding: None, language: Some("fr"), data: b"Bonjour" },
+///     Variant { media_type: None, encoding: Some("gzip"), language: None, data: b"..." },
+/// ];
+/// let asset = WebAsset {
+///     uri: "/hello.txt",
+///     data: b"Hello",
+///     text: None,
+///     media_type: "text/plain",
+///     cache_control: "no-cache",
+///     headers: &[],
+///     integrity: None,
+///     language: Some("en"),
+///     variants: VARIANTS,
+/// };
+///
+/// let variant = negotiate(&asset, "*/*", "", "fr-CH, fr;q=0.9, en;q=0.8");
+/// assert_eq!(variant.unwrap().data, b"Bonjour");
+/// assert!(negotiate(&asset, "*/*", "", "en, fr;q=0.5").is_none());
+/// ```
+pub fn negotiate(
+    asset: &WebAsset,
+    accept: &str,
+    accept_encoding: &str,
+    accept_language: &str,
+) -> Option<&'static Variant> {
+    best(
+        asset,
+        asset.variants.iter(),
+        accept,
+        accept_encoding,
+        accept_language,
+    )
+}
+
+fn best<I>(
+    asset: &WebAsset,
+    variants: I,
+    accept: &str,
+    accept_encoding: &str,
+    accept_language: &str,
+) -> Option<&'static Variant>
+where
+    I: Iterator<Item = &'static Variant>,
+{
+    // without a preference, the language of the asset itself is preferred
+    let language_quality = |language: Option<&str>| {
+        if accept_language.trim().is_empty() {
+            return if language.is_none() { 1.0 } else { 0.0 };
+        }
+
+        match language.or(asset.language) {
+            Some(language) => quality(accept_language, |range| {
+                range == "*" || language_matches(range, language)
+            }),
+            None => 0.001,
+        }
+    };
+
+    let mut best = None;
+    let mut best_score = (language_quality(None), 0.0, 0.0);
+    for variant in variants {
+        let media = match variant.media_type {
+            Some(media_type) => quality(accept, |range| range.eq_ignore_ascii_case(media_type)),
+            None => 0.0,
+        };
+        let encoding = match variant.encoding {
+            Some(coding) => encoding_quality(accept_encoding, coding),
+            None => 0.0,
+        };
+
+        if (variant.media_type.is_some() && media <= 0.0)
+            || (variant.encoding.is_some() && encoding <= 0.0)
+        {
+            continue;
+        }
+
+        let language = language_quality(variant.language);
+        let score = (language, media, encoding);
+        if (best.is_none() && score >= best_score) || score > best_score {
+            best = Some(variant);
+            best_score = score;
+        }
+    }
+
+    best
+}
+
+fn joined(headers: &HeaderMap, name: HeaderName) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The quality of a content coding in an `Accept-Encoding` header value,
+/// falling back to the `*` wildcard.
+fn encoding_quality(accept_encoding: &str, coding: &str) -> f32 {
+    let exact = quality(accept_encoding, |name| name.eq_ignore_ascii_case(coding));
+    let listed = accept_encoding.split(',').any(|item| {
+        let name = item.split(';').next().unwrap_or("").trim();
+        name.eq_ignore_ascii_case(coding)
+    });
+
+    if listed {
+        exact
+    } else {
+        quality(accept_encoding, |name| name == "*")
+    }
+}
+
+/// Whether a language range of an `Accept-Language` header matches the tag.
+///
+/// Ranges match tags they are a prefix of, and regional ranges such as
+/// `fr-CH` also match the primary language `fr`.
+fn language_matches(range: &str, tag: &str) -> bool {
+    let prefix = |a: &str, b: &str| {
+        a.len() < b.len() && b.as_bytes()[a.len()] == b'-' && b[..a.len()].eq_ignore_ascii_case(a)
+    };
+    range.eq_ignore_ascii_case(tag) || prefix(range, tag) || prefix(tag, range)
+}
+
+/// The highest quality of the items of a header value whose name matches.
+fn quality<F: Fn(&str) -> bool>(header: &str, matches: F) -> f32 {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or("").trim();
+            if name.is_empty() || !matches(name) {
+                return None;
+            }
+
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .map(|q| q.trim().parse::<f32>().unwrap_or(0.0))
+                .next()
+                .unwrap_or(1.0);
+            Some(q)
+        })
+        .fold(0.0, f32::max)
+}
+
+impl Normalization {
+    /// Finds the asset that a request path normalizes to.
+    ///
+    /// With [`TrailingSlash::Always`], a path without the trailing slash also
+    /// finds the asset of its directory, such as `/docs` finding `/docs/`.
+    ///
+    /// [`TrailingSlash::Always`]: ./enum.TrailingSlash.html#variant.Always
+    pub fn find<'a>(&self, assets: &'a [WebAsset], path: &str) -> Option<&'a WebAsset> {
+        let uri = self.normalize(path);
+        let find = |uri: &str| assets.iter().find(|asset| asset.uri == uri);
+        match self.trailing_slash {
+            TrailingSlash::Always if !uri.ends_with('/') => {
+                find(&uri).or_else(|| find(&format!("{}/", uri)))
+            }
+            _ => find(&uri),
+        }
+    }
+}
+
+/// Resolves request paths to web assets with fallback rules.
+///
+/// This is what a single-page app usually needs: unknown paths such as
+/// `/users/42` are served the app itself, while missing files under excluded
+/// prefixes such as `/static` are still not found.
+///
+/// ```
+/// # use includer::*;
+/// # const ASSETS: [WebAsset; 0] = [];
+/// # const ASSETS_NORMALIZATION: Normalization = Normalization {
+/// #     index_files: &["index.html"],
+/// #     trailing_slash: TrailingSlash::Never,
+/// #     strip_extensions: &[],
+/// #     lowercase: false,
+/// # };
+/// let resolver = Resolver::new(&ASSETS, &ASSETS_NORMALIZATION)
+///     .fallback("/")
+///     .exclude_prefix("/static/")
+///     .not_found("/404.html");
+///
+/// match resolver.resolve("/users/42") {
+///     Resolution::Found(asset) | Resolution::Fallback(asset) => println!("200 {}", asset.uri()),
+///     Resolution::Redirect(status, location) => println!("{} {}", status, location),
+///     Resolution::NotFound(_) => println!("404"),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Resolver<'a> {
+    assets: &'a [WebAsset],
+    normalization: &'a Normalization,
+    fallback: Option<String>,
+    excluded_prefixes: Vec<String>,
+    not_found: Option<String>,
+    redirect_trailing_slash: bool,
+    header_rules: &'a [HeaderRule],
+    redirects: &'a [Redirect],
+}
+
+impl<'a> Resolver<'a> {
+    /// Creates a `Resolver` without any fallback rules.
+    ///
+    /// Redirecting missing trailing slashes is enabled by default.
+    pub fn new(assets: &'a [WebAsset], normalization: &'a Normalization) -> Self {
+        Resolver {
+            assets,
+            normalization,
+            fallback: None,
+            excluded_prefixes: Vec::new(),
+            not_found: None,
+            redirect_trailing_slash: true,
+            header_rules: &[],
+            redirects: &[],
+        }
+    }
+
+    /// Sets the uri of the asset served for paths that don't match any asset.
+    pub fn fallback<S: Into<String>>(mut self, uri: S) -> Self {
+        self.fallback = Some(uri.into());
+        self
+    }
+
+    /// Excludes normalized paths starting with the prefix from the fallback.
+    pub fn exclude_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.excluded_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Sets the uri of the asset returned along with [`Resolution::NotFound`].
+    ///
+    /// [`Resolution::NotFound`]: ./enum.Resolution.html#variant.NotFound
+    pub fn not_found<S: Into<String>>(mut self, uri: S) -> Self {
+        self.not_found = Some(uri.into());
+        self
+    }
+
+    /// Sets whether paths missing the trailing slash of a directory redirect,
+    /// instead of being served the asset of the directory.
+    ///
+    /// Only has an effect with [`TrailingSlash::Always`].
+    ///
+    /// [`TrailingSlash::Always`]: ./enum.TrailingSlash.html#variant.Always
+    pub fn redirect_trailing_slash(mut self, redirect: bool) -> Self {
+        self.redirect_trailing_slash = redirect;
+        self
+    }
+
+    /// Sets the header rules applied by [`headers`] and [`response`].
+    ///
+    /// These are generated from a `_headers` file by `includer_codegen`.
+    ///
+    /// [`headers`]: #method.headers
+    /// [`response`]: #method.response
+    pub fn header_rules(mut self, rules: &'a [HeaderRule]) -> Self {
+        self.header_rules = rules;
+        self
+    }
+
+    /// Sets the redirect rules applied when resolving.
+    ///
+    /// These are generated from a `_redirects` file by `includer_codegen`.
+    /// Rules only apply when no asset matches the path, unless they are
+    /// forced.  The first matching rule is used.
+    pub fn redirects(mut self, redirects: &'a [Redirect]) -> Self {
+        self.redirects = redirects;
+        self
+    }
+
+    /// Resolves a request path.
+    ///
+    /// The path should not contain a query string or fragment.
+    pub fn resolve(&self, path: &str) -> Resolution<'a> {
+        let forced = self.redirects.iter().filter(|r| r.force);
+        if let Some(resolution) = self.redirect(forced, path) {
+            return resolution;
+        }
+
+        let uri = self.normalization.normalize(path);
+        if let Some(asset) = self.get(&uri) {
+            return Resolution::Found(asset);
+        }
+
+        if self.normalization.trailing_slash == TrailingSlash::Always && !uri.ends_with('/') {
+            let dir = format!("{}/", uri);
+            if let Some(asset) = self.get(&dir) {
+                if self.redirect_trailing_slash {
+                    return Resolution::Redirect(301, dir);
+                }
+                return Resolution::Found(asset);
+            }
+        }
+
+        let unforced = self.redirects.iter().filter(|r| !r.force);
+        if let Some(resolution) = self.redirect(unforced, path) {
+            return resolution;
+        }
+
+        let excluded = self
+            .excluded_prefixes
+            .iter()
+            .any(|p| uri.starts_with(p.as_str()));
+        if !excluded {
+            if let Some(asset) = self.fallback.as_ref().and_then(|f| self.get(f)) {
+                return Resolution::Fallback(asset);
+            }
+        }
+
+        Resolution::NotFound(self.not_found.as_ref().and_then(|uri| self.get(uri)))
+    }
+
+    /// Returns the headers of every header rule matching the request path.
+    pub fn headers(&self, path: &str) -> Vec<(&'static str, &'static str)> {
+        self.header_rules
+            .iter()
+            .filter(|rule| pattern_match(rule.pattern, path).is_some())
+            .flat_map(|rule| rule.headers.iter().cloned())
+            .collect()
+    }
+
+    /// Builds a response for a request path with the request headers.
+    ///
+    /// Assets are served with [`WebAsset::response`], redirects get an empty
+    /// body with a `Location` header, and not found responses have the not
+    /// found asset as the body if one is set.  The headers of all matching
+    /// header rules are added to every response.
+    ///
+    /// A redirect to a location that isn't a valid header value, such as one
+    /// with a control character captured from the request path, is a
+    /// `400 Bad Request` with an empty body.
+    ///
+    /// [`WebAsset::response`]: ./struct.WebAsset.html#method.response
+    pub fn response(&self, path: &str, request_headers: &HeaderMap) -> Response<&'static [u8]> {
+        let mut response = match self.resolve(path) {
+            Resolution::Found(asset) | Resolution::Fallback(asset) => {
+                asset.response(request_headers)
+            }
+            Resolution::Redirect(status, location) => {
+                let mut response = Response::new(&b""[..]);
+                match HeaderValue::from_str(&location) {
+                    Ok(location) => {
+                        *response.status_mut() =
+                            StatusCode::from_u16(status).expect("Invalid redirect status");
+                        response.headers_mut().insert(LOCATION, location);
+                    }
+                    Err(_) => *response.status_mut() = StatusCode::BAD_REQUEST,
+                }
+                response
+            }
+            Resolution::NotFound(asset) => {
+                let mut response = match asset {
+                    Some(asset) => asset.response(request_headers),
+                    None => Response::new(&b""[..]),
+                };
+                *response.status_mut() = StatusCode::NOT_FOUND;
+                response
+            }
+        };
+
+        for (name, value) in self.headers(path) {
+            let name = HeaderName::from_bytes(name.as_bytes()).expect("Invalid header name");
+            response
+                .headers_mut()
+                .append(name, HeaderValue::from_static(value));
+        }
+
+        response
+    }
+
+    fn redirect<'r, I>(&self, redirects: I, path: &str) -> Option<Resolution<'a>>
+    where
+        I: Iterator<Item = &'r Redirect>,
+    {
+        for redirect in redirects {
+            let captures = match pattern_match(redirect.from, path) {
+                Some(captures) => captures,
+                None => continue,
+            };
+
+            let to = substitute(redirect.to, &captures);
+
+            let target = || self.normalization.find(self.assets, &to);
+            match redirect.status {
+                200 => match target() {
+                    Some(asset) => return Some(Resolution::Fallback(asset)),
+                    None => continue,
+                },
+                404 => return Some(Resolution::NotFound(target())),
+                status => return Some(Resolution::Redirect(status, to)),
+            }
+        }
+
+        None
+    }
+
+    fn get(&self, uri: &str) -> Option<&'a WebAsset> {
+        self.assets.iter().find(|asset| asset.uri == uri)
+    }
+}
+
+/// The result of resolving a request path with a [`Resolver`].
+///
+/// [`Resolver`]: ./struct.Resolver.html
+#[derive(Debug)]
+pub enum Resolution<'a> {
+    /// The path matched an asset.
+    Found(&'a WebAsset),
+
+    /// The path didn't match, the fallback or rewritten asset should be served.
+    Fallback(&'a WebAsset),
+
+    /// The path should redirect with the status code to the location.
+    Redirect(u16, String),
+
+    /// Nothing matched, contains the custom not found asset if one is set.
+    NotFound(Option<&'a WebAsset>),
+}
+
+/// A rule from a `_headers` file, adding headers to every matching path.
+#[derive(Debug)]
+pub struct HeaderRule {
+    /// The path pattern, see [`Redirect::from`] for the syntax.
+    ///
+    /// [`Redirect::from`]: ./struct.Redirect.html#structfield.from
+    pub pattern: &'static str,
+
+    /// The headers added to matching paths.
+    pub headers: &'static [(&'static str, &'static str)],
+}
+
+/// A rule from a `_redirects` file.
+#[derive(Debug)]
+pub struct Redirect {
+    /// The path pattern to match.
+    ///
+    /// A segment starting with `:` is a placeholder matching any single
+    /// segment, and a trailing `*` segment matches the rest of the path as
+    /// the `splat` placeholder.
+    pub from: &'static str,
+
+    /// The location to redirect to, with `:name` placeholders substituted.
+    pub to: &'static str,
+
+    /// `200` rewrites to the asset at `to`, `404` serves it as not found, and
+    /// anything else is a redirect status code.
+    pub status: u16,
+
+    /// Whether the rule applies even when an asset matches the path.
+    pub force: bool,
+}
+
+/// Matches a path against a `_headers` or `_redirects` pattern, returning the
+/// captured placeholders.
+fn pattern_match<'p>(pattern: &'p str, path: &str) -> Option<Vec<(&'p str, String)>> {
+    let mut captures = Vec::new();
+    let mut segments = trim_trailing_slash(path).split('/');
+    for part in trim_trailing_slash(pattern).split('/') {
+        if part == "*" {
+            let rest: Vec<&str> = segments.collect();
+            captures.push(("splat", rest.join("/")));
+            return Some(captures);
+        }
+
+        let segment = segments.next()?;
+        if let Some(name) = part.strip_prefix(':') {
+            captures.push((name, segment.to_owned()));
+        } else if part != segment {
+            return None;
+        }
+    }
+
+    match segments.next() {
+        Some(_) => None,
+        None => Some(captures),
+    }
+}
+
+/// Substitutes the captured placeholders of a `_redirects` destination.
+///
+/// Placeholders are a `:` followed by the longest run of alphanumeric or `_`
+/// characters, and are replaced in a single pass so that captured values are
+/// never substituted again.  Placeholders that weren't captured are left as
+/// is.
+fn substitute(to: &str, captures: &[(&str, String)]) -> String {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut substituted = String::with_capacity(to.len());
+    let mut rest = to;
+    while let Some(colon) = rest.find(':') {
+        substituted.push_str(&rest[..colon]);
+        let after = &rest[colon + 1..];
+        let len = after.find(|c| !is_name(c)).unwrap_or(after.len());
+        let name = &after[..len];
+        match captures.iter().find(|(capture, _)| *capture == name) {
+            Some((_, value)) if !name.is_empty() => substituted.push_str(value),
+            _ => {
+                substituted.push(':');
+                substituted.push_str(name);
+            }
+        }
+        rest = &after[len..];
+    }
+
+    substituted.push_str(rest);
+    substituted
+}
+
+fn trim_trailing_slash(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAN: Normalization = Normalization {
+        index_files: &["index.html", "default.htm"],
+        trailing_slash: TrailingSlash::Always,
+        strip_extensions: &["html"],
+        lowercase: true,
+    };
+
+    const DEFAULT: Normalization = Normalization {
+        index_files: &["index.html"],
+        trailing_slash: TrailingSlash::Never,
+        strip_extensions: &[],
+        lowercase: false,
+    };
+
+    const ASSETS: [WebAsset; 3] = [
+        WebAsset {
+            uri: "/",
+            data: b"app",
+            text: None,
+            media_type: "text/html",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: None,
+            variants: &[],
+        },
+        WebAsset {
+            uri: "/docs/",
+            data: b"docs",
+            text: None,
+            media_type: "text/html",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: None,
+            variants: &[],
+        },
+        WebAsset {
+            uri: "/404",
+            data: b"not found",
+            text: None,
+            media_type: "text/html",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: None,
+            variants: &[],
+        },
+    ];
+
+    #[test]
+    fn resolve() {
+        let resolver = Resolver::new(&ASSETS, &CLEAN)
+            .fallback("/")
+            .exclude_prefix("/static/")
+            .not_found("/404");
+
+        match resolver.resolve("/docs/index.html") {
+            Resolution::Found(asset) => assert_eq!(asset.uri, "/docs/"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match resolver.resolve("/docs") {
+            Resolution::Redirect(301, location) => assert_eq!(location, "/docs/"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match resolver.resolve("/users/42") {
+            Resolution::Fallback(asset) => assert_eq!(asset.uri, "/"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match resolver.resolve("/static/missing.js") {
+            Resolution::NotFound(Some(asset)) => assert_eq!(asset.uri, "/404"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    const REDIRECTS: [Redirect; 3] = [
+        Redirect {
+            from: "/docs/*",
+            to: "/news/:splat",
+            status: 200,
+            force: true,
+        },
+        Redirect {
+            from: "/blog/:year/*",
+            to: "https://example.com/:year/:splat",
+            status: 302,
+            force: false,
+        },
+        Redirect {
+            from: "/gone",
+            to: "/404",
+            status: 404,
+            force: false,
+        },
+    ];
+
+    const HEADER_RULES: [HeaderRule; 2] = [
+        HeaderRule {
+            pattern: "/*",
+            headers: &[("X-Frame-Options", "DENY")],
+        },
+        HeaderRule {
+            pattern: "/docs/:page",
+            headers: &[("X-Robots-Tag", "noindex")],
+        },
+    ];
+
+    #[test]
+    fn patterns() {
+        assert_eq!(
+            pattern_match("/a/*", "/a/b/c"),
+            Some(vec![("splat", "b/c".into())])
+        );
+        assert_eq!(
+            pattern_match("/a/:b", "/a/c/"),
+            Some(vec![("b", "c".into())])
+        );
+        assert_eq!(pattern_match("/a/:b", "/a/c/d"), None);
+        assert_eq!(pattern_match("/", "/"), Some(vec![]));
+        assert_eq!(pattern_match("/a", "/b"), None);
+    }
+
+    #[test]
+    fn substitutions() {
+        let captures = vec![
+            ("year", "2018".to_string()),
+            ("splat", ":year/a".to_string()),
+        ];
+        assert_eq!(
+            substitute("/:yearly/:year/:splat", &captures),
+            "/:yearly/2018/:year/a"
+        );
+        assert_eq!(
+            substitute("https://example.com:8080/:", &captures),
+            "https://example.com:8080/:"
+        );
+    }
+
+    #[test]
+    fn resolve_redirects() {
+        let resolver = Resolver::new(&ASSETS, &CLEAN).redirects(&REDIRECTS);
+
+        match resolver.resolve("/blog/2018/hello/world") {
+            Resolution::Redirect(302, to) => assert_eq!(to, "https://example.com/2018/hello/world"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // forced, but the rewrite target doesn't exist so the asset is found
+        match resolver.resolve("/docs/index.html") {
+            Resolution::Found(asset) => assert_eq!(asset.uri, "/docs/"),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        match resolver.resolve("/gone") {
+            Resolution::NotFound(Some(asset)) => assert_eq!(asset.uri, "/404"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resolver_response() {
+        let resolver = Resolver::new(&ASSETS, &CLEAN)
+            .redirects(&REDIRECTS)
+            .header_rules(&HEADER_RULES);
+
+        let response = resolver.response("/docs/", &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-frame-options"], "DENY");
+        assert_eq!(*response.body(), b"docs");
+
+        let response = resolver.response("/blog/2018/post", &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[LOCATION],
+            "https://example.com/2018/post"
+        );
+
+        let response = resolver.response("/blog/2018/a\nb", &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.headers().get(LOCATION).is_none());
+
+        let response = resolver.response("/docs/missing", &HeaderMap::new());
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["x-robots-tag"], "noindex");
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert_eq!(encoding_quality("gzip, deflate, br", "br"), 1.0);
+        assert_eq!(encoding_quality("GZIP;q=0.5", "gzip"), 0.5);
+        assert_eq!(encoding_quality("gzip;q=0, *", "gzip"), 0.0);
+        assert_eq!(encoding_quality("identity, *;q=0.1", "br"), 0.1);
+        assert_eq!(encoding_quality("identity", "br"), 0.0);
+    }
+
+    #[test]
+    fn response() {
+        let asset = WebAsset {
+            uri: "/app.js",
+            data: b"app",
+            text: None,
+            media_type: "text/javascript",
+            cache_control: "immutable",
+            headers: &[("X-Content-Type-Options", "nosniff")],
+            integrity: None,
+            language: None,
+            variants: &[Variant {
+                media_type: None,
+                encoding: Some("gzip"),
+                language: None,
+                data: b"gz",
+            }],
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, "gzip, br".parse().unwrap());
+        let response = asset.response(&headers);
+        assert_eq!(*response.body(), b"gz");
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[CACHE_CONTROL], "immutable");
+        assert_eq!(response.headers()[VARY], "Accept-Encoding");
+        assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+    }
+
+    #[test]
+    fn plain_response() {
+        let asset = WebAsset {
+            uri: "/css/site.css",
+            data: b"body{}",
+            text: None,
+            media_type: "text/css",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: None,
+            variants: &[],
+        };
+
+        let response = asset.response(&HeaderMap::new());
+        assert_eq!(*response.body(), b"body{}");
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/css");
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert!(response.headers().get(VARY).is_none());
+    }
+
+    #[test]
+    fn zstd_and_deflate() {
+        let asset = WebAsset {
+            uri: "/app.js",
+            data: b"app",
+            text: None,
+            media_type: "text/javascript",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: None,
+            variants: &[
+                Variant {
+                    media_type: None,
+                    encoding: Some("zstd"),
+                    language: None,
+                    data: b"zst",
+                },
+                Variant {
+                    media_type: None,
+                    encoding: Some("deflate"),
+                    language: None,
+                    data: b"zz",
+                },
+            ],
+        };
+
+        assert_eq!(asset.data_zstd(), Some(&b"zst"[..]));
+        assert_eq!(asset.data_deflate(), Some(&b"zz"[..]));
+        assert_eq!(asset.data_gz(), None);
+        assert_eq!(
+            asset.encoded("gzip, deflate, br, zstd"),
+            (Some("zstd"), &b"zst"[..])
+        );
+        assert_eq!(asset.encoded("deflate"), (Some("deflate"), &b"zz"[..]));
+        assert_eq!(
+            asset.encoded("zstd;q=0.5, deflate"),
+            (Some("deflate"), &b"zz"[..])
+        );
+        assert_eq!(asset.encoded("gzip"), (None, &b"app"[..]));
+    }
+
+    #[test]
+    fn webp_response() {
+        let asset = WebAsset {
+            uri: "/logo.png",
+            data: b"png",
+            text: None,
+            media_type: "image/png",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: None,
+            variants: &[
+                Variant {
+                    media_type: Some("image/webp"),
+                    encoding: None,
+                    language: None,
+                    data: b"webp",
+                },
+                Variant {
+                    media_type: None,
+                    encoding: Some("gzip"),
+                    language: None,
+                    data: b"gz",
+                },
+            ],
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, "image/webp,*/*".parse().unwrap());
+        headers.insert(ACCEPT_ENCODING, "gzip".parse().unwrap());
+        let response = asset.response(&headers);
+        assert_eq!(*response.body(), b"webp");
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/webp");
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(response.headers()[VARY], "Accept, Accept-Encoding");
+
+        assert_eq!(asset.image("image/*, */*").1, b"png");
+        assert_eq!(asset.image("image/webp;q=0").1, b"png");
+    }
+
+    #[test]
+    fn negotiate_languages() {
+        const VARIANTS: &[Variant] = &[
+            Variant {
+                media_type: None,
+                encoding: None,
+                language: Some("fr"),
+                data: b"fr",
+            },
+            Variant {
+                media_type: None,
+                encoding: Some("br"),
+                language: Some("fr"),
+                data: b"fr br",
+            },
+            Variant {
+                media_type: None,
+                encoding: Some("br"),
+                language: None,
+                data: b"br",
+            },
+        ];
+        let mut asset = WebAsset {
+            uri: "/README.md",
+            data: b"en",
+            text: None,
+            media_type: "text/markdown",
+            cache_control: "no-cache",
+            headers: &[],
+            integrity: None,
+            language: Some("en"),
+            variants: VARIANTS,
+        };
+
+        let data = |asset: &WebAsset, encoding, language| {
+            negotiate(asset, "*/*", encoding, language).map_or(&b"en"[..], |v| v.data)
+        };
+        assert_eq!(data(&asset, "", "fr-CA, en;q=0.5"), b"fr");
+        assert_eq!(data(&asset, "br", "fr"), b"fr br");
+        assert_eq!(data(&asset, "br", "en, fr;q=0.5"), b"br");
+        assert_eq!(data(&asset, "gzip", "de, en;q=0.1"), b"en");
+        assert_eq!(data(&asset, "br", ""), b"br");
+
+        asset.language = None;
+        assert_eq!(data(&asset, "", "de, fr;q=0.1"), b"fr");
+        assert_eq!(data(&asset, "", "de"), b"en");
+    }
+
+    #[test]
+    fn normalize_default() {
+        assert_eq!(DEFAULT.normalize("/index.html"), "/");
+        assert_eq!(DEFAULT.normalize("/docs/"), "/docs");
+        assert_eq!(DEFAULT.normalize("/docs/index.html"), "/docs");
+        assert_eq!(DEFAULT.normalize("/About.html"), "/About.html");
+    }
+
+    #[test]
+    fn normalize_clean() {
+        assert_eq!(CLEAN.normalize("/"), "/");
+        assert_eq!(CLEAN.normalize("/Docs/Default.htm"), "/docs/");
+        assert_eq!(CLEAN.normalize("/docs/"), "/docs/");
+        assert_eq!(CLEAN.normalize("/About.html"), "/about");
+        assert_eq!(CLEAN.normalize("/.html"), "/.html");
+        assert_eq!(CLEAN.find(&ASSETS, "/Docs").unwrap().uri, "/docs/");
+        assert!(DEFAULT.find(&ASSETS, "/docs").is_none());
+    }
+
+    #[test]
+    fn serve_missing_trailing_slash() {
+        let resolver = Resolver::new(&ASSETS, &CLEAN).redirect_trailing_slash(false);
+        match resolver.resolve("/docs") {
+            Resolution::Found(asset) => assert_eq!(asset.uri, "/docs/"),
+            other => panic!("unexpected {:?}", other),
+        }
     }
 }
@@ -0,0 +1,102 @@
+//! Content codings of precompressed asset versions.
+use std::io::{Read, Write};
+use utils;
+
+/// A content coding that assets can have a precompressed version in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum CompressionType {
+    Gzip,
+    Brotli,
+    Zstd,
+    Deflate,
+}
+
+impl CompressionType {
+    /// Every coding, in the order that equally preferred codings are picked.
+    pub(super) const ALL: [CompressionType; 4] = [
+        CompressionType::Brotli,
+        CompressionType::Zstd,
+        CompressionType::Gzip,
+        CompressionType::Deflate,
+    ];
+
+    /// The extension of sidecar files in this coding.
+    pub(super) fn extension(&self) -> &'static str {
+        match self {
+            CompressionType::Gzip => "gz",
+            CompressionType::Brotli => "br",
+            CompressionType::Zstd => "zst",
+            CompressionType::Deflate => "zz",
+        }
+    }
+
+    /// The name of the coding in `Content-Encoding`.
+    pub(super) fn encoding(&self) -> &'static str {
+        match self {
+            CompressionType::Gzip => "gzip",
+            CompressionType::Brotli => "br",
+            CompressionType::Zstd => "zstd",
+            CompressionType::Deflate => "deflate",
+        }
+    }
+
+    /// Compresses the data at the highest level of the coding.
+    pub(super) fn compress(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+
+        utils::cached(self.encoding(), data, |data| {
+            let compressed = match self {
+                CompressionType::Gzip => {
+                    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+                    encoder.write_all(&data).and_then(|_| encoder.finish())
+                }
+                CompressionType::Deflate => {
+                    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+                    encoder.write_all(&data).and_then(|_| encoder.finish())
+                }
+                CompressionType::Brotli => {
+                    let mut out = Vec::new();
+                    {
+                        let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+                        encoder.write_all(&data).map_err(|e| e.to_string())?;
+                    }
+                    Ok(out)
+                }
+                CompressionType::Zstd => zstd::encode_all(&data[..], 19),
+            };
+            compressed.map_err(|e| e.to_string())
+        })
+    }
+
+    /// Decompresses data in the coding.
+    pub(super) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut out = Vec::new();
+        match self {
+            CompressionType::Gzip => flate2::read::GzDecoder::new(data).read_to_end(&mut out),
+            CompressionType::Deflate => flate2::read::ZlibDecoder::new(data).read_to_end(&mut out),
+            CompressionType::Brotli => brotli::Decompressor::new(data, 4096).read_to_end(&mut out),
+            CompressionType::Zstd => {
+                zstd::Decoder::new(data).and_then(|mut d| d.read_to_end(&mut out))
+            }
+        }
+        .map_err(|e| e.to_string())?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = b"body { color: red; } ".repeat(64);
+        for compression in CompressionType::ALL.iter() {
+            let compressed = compression.compress(data.clone()).unwrap();
+            assert!(compressed.len() < data.len());
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+            assert!(compression.decompress(b"not compressed").is_err());
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Build-time optimization of PNG and JPEG images.
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use utils;
+
+/// Whether the file is an image that can be optimized.
+pub(super) fn is_image(path: &Path) -> bool {
+    matches!(
+        ImageFormat::from_path(path),
+        Ok(ImageFormat::Png) | Ok(ImageFormat::Jpeg)
+    )
+}
+
+/// Whether the file is a PNG image that can be recompressed.
+pub(super) fn is_png(path: &Path) -> bool {
+    ImageFormat::from_path(path).ok() == Some(ImageFormat::Png)
+}
+
+/// Recompresses a PNG losslessly.
+///
+/// Returns `None` if the result isn't smaller than the PNG.
+pub(super) fn recompress(data: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let optimized = utils::cached("png", data.to_vec(), |data| {
+        let options = oxipng::Options::from_preset(2);
+        oxipng::optimize_from_memory(&data, &options).map_err(|e| e.to_string())
+    })?;
+
+    Ok(if optimized.len() < data.len() {
+        Some(optimized)
+    } else {
+        None
+    })
+}
+
+/// Converts an image into a lossless WebP.
+///
+/// Returns `None` if the WebP isn't smaller than the image.
+pub(super) fn webp(data: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    let webp = utils::cached("webp", data.to_vec(), |data| {
+        let image = image::load_from_memory(&data).map_err(|e| e.to_string())?;
+        let image = DynamicImage::ImageRgba8(image.to_rgba8());
+
+        let mut webp = Vec::new();
+        image
+            .write_with_encoder(WebPEncoder::new_lossless(&mut webp))
+            .map_err(|e| e.to_string())?;
+        Ok(webp)
+    })?;
+
+    Ok(if webp.len() < data.len() {
+        Some(webp)
+    } else {
+        None
+    })
+}
+
+/// Writes a copy of the image resized to every width smaller than its own,
+/// keeping its aspect ratio.
+///
+/// The copies are written into `$OUT_DIR/includer/images` with the file name
+/// of the image and a `-{width}w` suffix, such as `hero-640w.jpg`, and only
+/// when the image changed.  The image is only decoded if a copy is missing.
+/// Returns the width and path of every copy.
+pub(super) fn resized(path: &Path, widths: &[u32]) -> Vec<(u32, PathBuf)> {
+    let data = fs::read(path).expect("Unable to read image to resize it");
+    let format = ImageFormat::from_path(path).expect("Unable to resize an unknown image format");
+    let error = |e: image::ImageError| format!("Unable to resize `{}`: {}", path.display(), e);
+    let (w, h) = ImageReader::with_format(Cursor::new(&data), format)
+        .into_dimensions()
+        .unwrap_or_else(|e| panic!("{}", error(e)));
+
+    let out_dir = env::var_os("OUT_DIR").expect("OUT_DIR must be set to resize images");
+    let hash = format!("{:x}", Sha256::digest(&data));
+    let dir = Path::new(&out_dir)
+        .join("includer")
+        .join("images")
+        .join(&hash[..16]);
+    fs::create_dir_all(&dir).expect("Unable to create resized images directory");
+
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let ext = path.extension().unwrap().to_string_lossy();
+    let mut image: Option<DynamicImage> = None;
+    widths
+        .iter()
+        .filter(|&&width| width < w)
+        .map(|&width| {
+            let resized = dir.join(format!("{}-{}w.{}", stem, width, ext));
+            if !resized.exists() {
+                let image = image.get_or_insert_with(|| {
+                    image::load_from_memory_with_format(&data, format)
+                        .unwrap_or_else(|e| panic!("{}", error(e)))
+                });
+                let height = (u64::from(h) * u64::from(width) / u64::from(w)).max(1) as u32;
+                let mut out = Cursor::new(Vec::new());
+                image
+                    .resize_exact(width, height, FilterType::Lanczos3)
+                    .write_to(&mut out, format)
+                    .unwrap_or_else(|e| panic!("{}", error(e)));
+                fs::write(&resized, out.into_inner()).expect("Unable to write resized image");
+            }
+            (width, resized)
+        })
+        .collect()
+}
+
+/// Inserts the width before the extension of the last uri segment.
+pub(super) fn resized_uri(uri: &str, width: u32) -> String {
+    let split = uri.rfind('/').map_or(0, |i| i + 1);
+    let (dir, file) = uri.split_at(split);
+    match file.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}{}-{}w{}", dir, &file[..dot], width, &file[dot..]),
+        _ => format!("{}{}-{}w", dir, file, width),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(width, height, Rgba([200, 40, 40, 255]));
+        let mut out = Cursor::new(Vec::new());
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut out, ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn webp_is_lossless() {
+        let data = png(64, 32);
+        let webp = webp(&data).unwrap().expect("webp should be smaller");
+        let decoded = image::load_from_memory(&webp).unwrap();
+        assert_eq!(
+            decoded.to_rgba8(),
+            image::load_from_memory(&data).unwrap().to_rgba8()
+        );
+    }
+
+    #[test]
+    fn recompressed_png_is_identical() {
+        let data = png(64, 32);
+        let optimized = match recompress(&data).unwrap() {
+            Some(optimized) => optimized,
+            None => return,
+        };
+        assert!(optimized.len() < data.len());
+        assert_eq!(
+            image::load_from_memory(&optimized).unwrap().to_rgba8(),
+            image::load_from_memory(&data).unwrap().to_rgba8()
+        );
+    }
+
+    #[test]
+    fn resized_uris() {
+        assert_eq!(resized_uri("/img/hero.jpg", 640), "/img/hero-640w.jpg");
+        assert_eq!(resized_uri("/hero", 640), "/hero-640w");
+    }
+}
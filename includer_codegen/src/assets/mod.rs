@@ -1,31 +1,81 @@
+#[cfg(any(feature = "web", feature = "compress"))]
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+mod compression;
+#[cfg(feature = "images")]
+mod images;
+#[cfg(feature = "web")]
+mod netlify;
+#[cfg(feature = "web")]
+mod rewrite;
 #[cfg(feature = "web")]
 pub mod web;
 
+#[cfg(feature = "compress")]
+use self::compression::CompressionType;
 use proc_macro2::{Ident, Span};
+use std::collections::HashSet;
 use std::fmt;
+#[cfg(feature = "compress")]
+use std::fs;
+use std::iter;
 use std::path::{Path, PathBuf};
 use utils;
-use utils::Filter;
-use utils::Filter::*;
-use utils::FilterListType;
-use utils::FilterListType::*;
-use walkdir::WalkDir;
+use utils::{EmbedFile, Filter, FilterRule, Mount, Pack};
+use CollisionPolicy;
+use Entry;
 use Pipeline;
+use Transform;
 
 #[cfg(feature = "web")]
 pub use self::web::*;
 
-struct AssetInfo {
-    path: String,
-    clean_path: String,
+/// A codec that [`Assets`] can be compressed at rest with.
+///
+/// The `includer` crate needs the cargo feature of the same name to
+/// decompress them.
+///
+/// [`Assets`]: ./struct.Assets.html
+#[cfg(feature = "compress")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Codec {
+    /// The zlib format, which decompresses the fastest.
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+#[cfg(feature = "compress")]
+impl Codec {
+    fn compression_type(self) -> CompressionType {
+        match self {
+            Codec::Deflate => CompressionType::Deflate,
+            Codec::Brotli => CompressionType::Brotli,
+            Codec::Zstd => CompressionType::Zstd,
+        }
+    }
+
+    /// The name of the variant of `includer::Codec`.
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Deflate => "Deflate",
+            Codec::Brotli => "Brotli",
+            Codec::Zstd => "Zstd",
+        }
+    }
 }
 
 pub struct Assets {
     ident: String,
-    prefix: String,
-    path: PathBuf,
-    filters: Vec<Filter>,
-    filter_list_type: FilterListType,
+    mount: Mount,
+    mounts: Vec<Mount>,
+    overlay: CollisionPolicy,
+    files: Vec<Entry>,
+    aliases: Vec<(String, String)>,
+    transforms: Vec<(FilterRule, Box<dyn Transform>)>,
+    alignments: Vec<(FilterRule, usize)>,
+    texts: Vec<FilterRule>,
+    #[cfg(feature = "compress")]
+    codec: Option<Codec>,
 }
 
 impl Assets {
@@ -35,10 +85,16 @@ impl Assets {
     pub fn new<S: Into<String>, P: Into<PathBuf>>(identifier: S, path: P) -> Self {
         Assets {
             ident: identifier.into(),
-            prefix: "/".to_string(),
-            path: path.into(),
-            filters: Vec::new(),
-            filter_list_type: Blacklist,
+            mount: Mount::new(path),
+            mounts: Vec::new(),
+            overlay: CollisionPolicy::LastWins,
+            files: Vec::new(),
+            aliases: Vec::new(),
+            transforms: Vec::new(),
+            alignments: Vec::new(),
+            texts: Vec::new(),
+            #[cfg(feature = "compress")]
+            codec: None,
         }
     }
 
@@ -104,7 +160,7 @@ impl Assets {
     ///
     /// [`FilterRule::regex`]: ./utils/enum.FilterRule.html#method.regex
     pub fn filter(mut self, filter: Filter) -> Self {
-        self.filters.push(filter);
+        self.mount = self.mount.filter(filter);
         self
     }
 
@@ -116,25 +172,190 @@ impl Assets {
     ///
     /// Defaults to `"/"`
     pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
-        self.prefix = prefix.into();
+        self.mount = self.mount.prefix(prefix);
         self
     }
 
     /// Sets the path to the assets directory.
     pub fn set_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
-        self.path = path.into();
+        self.mount = self.mount.set_path(path);
         self
     }
 
     /// Set the filter list type to a blacklist.
     pub fn blacklist(mut self) -> Self {
-        self.filter_list_type = Blacklist;
+        self.mount = self.mount.blacklist();
         self
     }
 
     /// Set the filter list type to a whitelist.
     pub fn whitelist(mut self) -> Self {
-        self.filter_list_type = Whitelist;
+        self.mount = self.mount.whitelist();
+        self
+    }
+
+    /// Mounts another directory into the same asset set.
+    ///
+    /// The entries of every mount are merged into a single generated array,
+    /// the directory passed to [`new`] being the first mount.  See [`overlay`]
+    /// for what happens when mounts share a uri.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// Assets::new("ASSETS", "../web/dist")
+    ///     .mount(Mount::new("../branding").prefix("/branding"))
+    ///     .mount(Mount::new("../docs/book").prefix("/docs"));
+    /// ```
+    ///
+    /// [`new`]: #method.new
+    /// [`overlay`]: #method.overlay
+    pub fn mount(mut self, mount: Mount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Sets which entry is kept when multiple mounts have the same uri.
+    ///
+    /// Defaults to [`CollisionPolicy::LastWins`] so that later mounts overlay
+    /// the earlier ones.
+    ///
+    /// [`CollisionPolicy::LastWins`]: ./enum.CollisionPolicy.html#variant.LastWins
+    pub fn overlay(mut self, policy: CollisionPolicy) -> Self {
+        self.overlay = policy;
+        self
+    }
+
+    /// Adds a single file at an explicit uri.
+    ///
+    /// The file doesn't need to be inside of any mounted directory, and it
+    /// overlays mounted entries with the same uri like a mount would.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// Assets::new("ASSETS", "../web/dist").add_file("../LICENSE", "/LICENSE");
+    /// ```
+    pub fn add_file<P: Into<PathBuf>, S: Into<String>>(mut self, path: P, uri: S) -> Self {
+        self.files.push(Entry {
+            uri: uri.into(),
+            path: path.into(),
+            logical_uri: None,
+        });
+        self
+    }
+
+    /// Makes the asset at `to_uri` also available at `from_uri`.
+    ///
+    /// Both entries share the same embedded data, it is not included twice.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// Assets::new("ASSETS", "../web/dist").alias("/favicon.ico", "/static/favicon.ico");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if no entry has the uri `to_uri`.
+    pub fn alias<S: Into<String>, T: Into<String>>(mut self, from_uri: S, to_uri: T) -> Self {
+        self.aliases.push((from_uri.into(), to_uri.into()));
+        self
+    }
+
+    /// Transforms every file matching the rule before embedding it.
+    ///
+    /// Every matching transform applies, in the order that they were added.
+    /// The output is written into `OUT_DIR` and embedded instead of the file.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// # use std::path::Path;
+    /// #
+    /// let trim = |_: &Path, data: Vec<u8>| -> Result<Vec<u8>, String> {
+    ///     let text = String::from_utf8(data).map_err(|e| e.to_string())?;
+    ///     Ok(text.trim().as_bytes().to_vec())
+    /// };
+    ///
+    /// Assets::new("ASSETS", "../resources").transform(FilterRule::extension("txt"), trim);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a transform fails.
+    pub fn transform<T: Transform + 'static>(mut self, rule: FilterRule, transform: T) -> Self {
+        self.transforms.push((rule, Box::new(transform)));
+        self
+    }
+
+    /// Aligns the embedded data of every file matching the rule, such as to 8,
+    /// 16, 64 or the page size.
+    ///
+    /// This allows zero-copy data like lookup tables or serialized archives to
+    /// be read in place, see `Asset::as_aligned`.  Rules are applied in the
+    /// order that they were added, the first matching rule determines the
+    /// alignment.  Data is aligned to 1 byte by default.  Assets that are
+    /// compressed at rest are only aligned before they're decompressed.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// Assets::new("ASSETS", "../resources")
+    ///     .align(FilterRule::extension("rkyv"), 16)
+    ///     .align(FilterRule::extension("bin"), 4096);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the alignment isn't a power of two up to 2^29.
+    pub fn align(mut self, rule: FilterRule, alignment: usize) -> Self {
+        utils::check_alignment(alignment);
+        self.alignments.push((rule, alignment));
+        self
+    }
+
+    /// Embeds every file matching the rule as text, available from
+    /// `Asset::text`.
+    ///
+    /// The files are validated as UTF-8, after any transforms, so that they
+    /// can be borrowed as strings without checking them at runtime.  Text is
+    /// never compressed at rest.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// Assets::new("ASSETS", "../resources")
+    ///     .text(FilterRule::extension("sql"))
+    ///     .text(FilterRule::extension("lua"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a matching file isn't valid UTF-8.
+    pub fn text(mut self, rule: FilterRule) -> Self {
+        self.texts.push(rule);
+        self
+    }
+
+    /// Stores every asset compressed with the codec, to decompress on the
+    /// first access.
+    ///
+    /// Decompressed assets are kept in memory for the rest of the program,
+    /// so this suits assets that are rarely read.  Use `Asset::contents` to
+    /// read them, as `Asset::data` is the compressed data.  Assets that don't
+    /// get smaller and [`text`] are stored as is.  Requires the `compress`
+    /// cargo feature, and the feature of the codec in the `includer` crate.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// Assets::new("TEMPLATES", "../templates").compression(Codec::Zstd);
+    /// ```
+    ///
+    /// [`text`]: #method.text
+    #[cfg(feature = "compress")]
+    pub fn compression(mut self, codec: Codec) -> Self {
+        self.codec = Some(codec);
         self
     }
 
@@ -148,99 +369,333 @@ impl Assets {
 
 impl fmt::Display for Assets {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut entries = Vec::new();
-        for maybe_entry in WalkDir::new(&self.path) {
-            let entry = maybe_entry.expect("Couldn't read DirEntry");
-
-            // We don't have special rules for directories, but we can't use
-            // walkdir's entry filter because we don't want files under
-            // directories to be skipped.
-            if entry.file_type().is_dir() {
-                utils::watch_path(entry.path());
-                continue;
-            }
+        write!(f, "{}", self.generate(&self.entries()))
+    }
+}
 
-            if self.filters.is_empty() {
-                match self.filter_list_type {
-                    Whitelist => break,
-                    Blacklist => {
-                        utils::watch_path(entry.path());
-                        entries.push(PathBuf::from(entry.path()));
-                        continue;
-                    }
-                }
-            }
+impl Pipeline for Assets {
+    fn entries(&self) -> Vec<Entry> {
+        let mut mounts: Vec<Vec<Entry>> = iter::once(&self.mount)
+            .chain(&self.mounts)
+            .map(Mount::entries)
+            .collect();
+        mounts.push(utils::watch_files(&self.files));
 
-            let mut matched = true;
-            for filter in &self.filters {
-                // Skip all filters that don't match the entry
-                if !filter.matches(entry.path()) {
-                    continue;
-                }
+        let mut entries = utils::merge_mounts(mounts, self.overlay);
+        utils::add_aliases(&mut entries, &self.aliases);
 
-                if let Exclude(_) = filter {
-                    matched = false;
-                }
+        if entries.is_empty() {
+            panic!("No assets were matched, something is wrong")
+        }
 
-                break;
-            }
+        entries
+    }
 
-            if matched {
-                utils::watch_path(entry.path());
-                entries.push(PathBuf::from(entry.path()));
-            }
-        }
+    fn generate(&self, entries: &[Entry]) -> String {
+        generate(self, entries, None)
+    }
 
-        let asset_info: Vec<AssetInfo> = entries
-            .iter()
-            .map(|p| AssetInfo {
-                path: utils::path_to_string(p),
-                clean_path: normalize_path(p, &self.path, &self.prefix),
-            }).collect();
+    fn generate_packed(&self, entries: &[Entry], pack: &mut Pack) -> String {
+        generate(self, entries, Some(pack))
+    }
+}
 
-        if asset_info.is_empty() {
-            panic!("No assets were matched, something is wrong")
-        }
+fn generate(builder: &Assets, entries: &[Entry], pack: Option<&mut Pack>) -> String {
+    let (paths, _) = utils::unique_paths(entries);
+    let data: Vec<_> = paths
+        .iter()
+        .map(|path| compressed(builder, path, utils::transform(path, &builder.transforms)))
+        .collect();
+    generate_asset_const(builder, entries, &data, pack)
+}
+
+/// Returns the path of the data to embed and the codec it's compressed
+/// with, if it's compressed at rest.
+///
+/// `data` is the transformed data of the file, if it was transformed.
+#[cfg(feature = "compress")]
+fn compressed(
+    builder: &Assets,
+    path: &Path,
+    data: Option<Vec<u8>>,
+) -> (PathBuf, Option<&'static str>) {
+    let codec = match builder.codec {
+        Some(codec) if !is_text(builder, path) => codec,
+        _ => return (stored(path, data), None),
+    };
 
-        let code = generate_asset_const(&self.ident, asset_info);
-        write!(f, "{}", code)
+    let source = match data {
+        Some(ref data) => data.clone(),
+        None => fs::read(path).expect("Unable to read asset to compress it"),
+    };
+    let len = source.len();
+    match codec.compression_type().compress(source) {
+        Ok(compressed) if compressed.len() < len => {
+            (utils::write_out_dir(&compressed), Some(codec.name()))
+        }
+        Ok(_) => (stored(path, data), None),
+        Err(e) => panic!("Unable to compress `{}`: {}", path.display(), e),
     }
 }
 
-impl Pipeline for Assets {}
+#[cfg(not(feature = "compress"))]
+fn compressed(_: &Assets, path: &Path, data: Option<Vec<u8>>) -> (PathBuf, Option<&'static str>) {
+    (stored(path, data), None)
+}
 
-fn normalize_path(path: &Path, dir: &Path, prefix: &str) -> String {
-    let relative = path.strip_prefix(&dir).expect("Couldn't strip path prefix");
-    let path = PathBuf::from("/").join(prefix).join(&relative);
-    utils::path_to_string(path)
+/// Returns the path of the data to embed, which is the file itself unless it
+/// was transformed.
+fn stored(path: &Path, data: Option<Vec<u8>>) -> PathBuf {
+    match data {
+        Some(data) => utils::write_out_dir(&data),
+        None => path.to_path_buf(),
+    }
 }
 
-fn generate_asset_const(ident_str: &str, raw_assets: Vec<AssetInfo>) -> String {
-    let len = raw_assets.len();
+fn is_text(builder: &Assets, path: &Path) -> bool {
+    builder.texts.iter().any(|rule| rule.matches(path))
+}
+
+fn generate_asset_const(
+    builder: &Assets,
+    entries: &[Entry],
+    data_paths: &[(PathBuf, Option<&'static str>)],
+    pack: Option<&mut Pack>,
+) -> String {
+    let (paths, indices) = utils::unique_paths(entries);
+    let mut compressions = Vec::new();
     let mut structs = Vec::new();
 
-    for AssetInfo { path, clean_path } in raw_assets {
-        structs.push(quote! {
-            Asset {
-                uri: #clean_path,
-                data: include_bytes!(#path),
+    let files: Vec<_> = paths
+        .iter()
+        .zip(data_paths)
+        .map(|(path, (data, _))| EmbedFile {
+            alignment: utils::alignment(&builder.alignments, path),
+            text: is_text(builder, path),
+            ..EmbedFile::new(path, data.as_path())
+        })
+        .collect();
+    let embedded = utils::embed(&files, pack);
+    let mut data = embedded.items;
+    let blob_indices = embedded.indices;
+
+    let mut caches = HashSet::new();
+    for ((_, codec), &k) in data_paths.iter().zip(&blob_indices) {
+        compressions.push(match codec {
+            Some(codec) => {
+                let cache_ident = Ident::new(&format!("CACHE_{}", k), Span::call_site());
+                let codec = Ident::new(codec, Span::call_site());
+                if caches.insert(k) {
+                    data.push(quote! {
+                        static #cache_ident: ::std::sync::OnceLock<::std::vec::Vec<u8>> =
+                            ::std::sync::OnceLock::new();
+                    });
+                }
+                quote! {Some(::includer::Compression { codec: ::includer::Codec::#codec, cache: &#cache_ident })}
             }
+            None => quote! {None},
         });
     }
 
-    let ident = Ident::new(ident_str, Span::call_site());
+    for (Entry { uri, .. }, i) in entries.iter().zip(indices) {
+        let data_ref = &embedded.refs[blob_indices[i]];
+        let text = match embedded.texts[blob_indices[i]] {
+            Some(ref text) if is_text(builder, paths[i]) => quote! {Some(#text)},
+            _ => quote! {None},
+        };
+        let compression = &compressions[i];
+        structs.push(quote! {
+            ::includer::Asset {
+                uri: #uri,
+                data: #data_ref,
+                text: #text,
+                compression: #compression,
+            }
+        });
+    }
 
-    let tokens = quote! {
-        const #ident: [Asset; #len] = [#(#structs),*];
-    };
+    let tokens = utils::asset_array(&builder.ident, "Asset", &data, &structs, embedded.external);
 
     format!("{}", tokens)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::fs;
+    use utils::path_to_string;
+
+    fn generated(builder: &Assets) -> String {
+        builder.generate(&builder.entries())
+    }
+
+    fn uris(builder: &Assets) -> Vec<(String, PathBuf)> {
+        let entries = builder.entries().into_iter();
+        entries.map(|entry| (entry.uri, entry.path)).collect()
+    }
+
+    #[test]
+    fn aliases_share_data() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("favicon.ico"), "icon").unwrap();
+
+        let builder = Assets::new("ASSETS", dir).alias("/favicon-32.ico", "/favicon.ico");
+        let code = generated(&builder);
+        let path = path_to_string(dir.join("favicon.ico"));
+        let data = quote! {
+            static DATA_0: [u8; 4usize] = *include_bytes!(#path);
+        };
+        assert!(code.contains(&data.to_string()));
+        assert!(!code.contains("DATA_1"));
+        for uri in &["/favicon.ico", "/favicon-32.ico"] {
+            let asset = quote! {
+                ::includer::Asset {
+                    uri: #uri,
+                    data: &DATA_0,
+                    text: None,
+                    compression: None,
+                }
+            };
+            assert!(code.contains(&asset.to_string()));
+        }
+    }
+
     #[test]
-    fn it_works() {
-        assert_eq!(1 + 1, 2);
+    fn aligned_statics() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("table.bin"), "abcd").unwrap();
+        fs::write(dir.join("page.bin"), "efgh").unwrap();
+        fs::write(dir.join("notes.txt"), "ijk").unwrap();
+
+        // the first matching rule determines the alignment
+        let builder = Assets::new("ASSETS", dir)
+            .align(FilterRule::regex("page"), 4096)
+            .align(FilterRule::extension("bin"), 16);
+        let code = generated(&builder);
+        let (page, table, notes) = (
+            path_to_string(dir.join("page.bin")),
+            path_to_string(dir.join("table.bin")),
+            path_to_string(dir.join("notes.txt")),
+        );
+        let statics = [
+            quote! {
+                static DATA_0: [u8; 3usize] = *include_bytes!(#notes);
+            },
+            quote! {
+                static DATA_1: Align4096<[u8; 4usize]> = Align4096(*include_bytes!(#page));
+            },
+            quote! {
+                static DATA_2: Align16<[u8; 4usize]> = Align16(*include_bytes!(#table));
+            },
+        ];
+        for data in &statics {
+            assert!(code.contains(&data.to_string()));
+        }
+        assert!(code.contains(&quote! {data: &DATA_2.0,}.to_string()));
+    }
+
+    #[test]
+    fn text_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("query.sql"), "select 1").unwrap();
+        fs::write(dir.join("query.bin"), "select 2").unwrap();
+
+        let builder = Assets::new("ASSETS", dir).text(FilterRule::extension("sql"));
+        let code = generated(&builder);
+        let (bin, sql) = (
+            path_to_string(dir.join("query.bin")),
+            path_to_string(dir.join("query.sql")),
+        );
+        let items = [
+            quote! {
+                static DATA_0: [u8; 8usize] = *include_bytes!(#bin);
+            },
+            quote! {
+                static DATA_1: &str = include_str!(#sql);
+            },
+            quote! {
+                ::includer::Asset {
+                    uri: "/query.bin",
+                    data: &DATA_0,
+                    text: None,
+                    compression: None,
+                }
+            },
+            quote! {
+                ::includer::Asset {
+                    uri: "/query.sql",
+                    data: DATA_1.as_bytes(),
+                    text: Some(DATA_1),
+                    compression: None,
+                }
+            },
+        ];
+        for item in &items {
+            assert!(code.contains(&item.to_string()));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "is not valid UTF-8")]
+    fn invalid_text() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("query.sql"), b"select \xff").unwrap();
+
+        generated(&Assets::new("ASSETS", dir).text(FilterRule::extension("sql")));
+    }
+
+    #[test]
+    fn mount_precedence() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (dist, branding) = (tmp.path().join("dist"), tmp.path().join("branding"));
+        fs::create_dir_all(dist.join("img")).unwrap();
+        fs::create_dir_all(&branding).unwrap();
+        fs::write(dist.join("img/logo.png"), "dist").unwrap();
+        fs::write(dist.join("index.html"), "dist").unwrap();
+        fs::write(branding.join("logo.png"), "branding").unwrap();
+        let license = tmp.path().join("LICENSE");
+        fs::write(&license, "license").unwrap();
+
+        let mounted = || {
+            Assets::new("ASSETS", &dist)
+                .mount(Mount::new(&branding).prefix("/img"))
+                .add_file(&license, "/index.html")
+        };
+        // later mounts and added files overlay the earlier mounts by default
+        assert_eq!(
+            uris(&mounted()),
+            vec![
+                ("/img/logo.png".to_string(), branding.join("logo.png")),
+                ("/index.html".to_string(), license.clone()),
+            ]
+        );
+        assert_eq!(
+            uris(&mounted().overlay(CollisionPolicy::FirstWins)),
+            vec![
+                ("/img/logo.png".to_string(), dist.join("img/logo.png")),
+                ("/index.html".to_string(), dist.join("index.html")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn uncompressed_assets() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.sql"), "select 1 union select 1 union select 1").unwrap();
+        fs::write(dir.join("b.bin"), "abcd").unwrap();
+
+        // text is never compressed, and data that doesn't get smaller is stored
+        let builder = Assets::new("ASSETS", dir)
+            .text(FilterRule::extension("sql"))
+            .compression(Codec::Zstd);
+        let code = generated(&builder);
+        assert!(!code.contains("CACHE_"));
+        assert!(code.contains(&path_to_string(dir.join("b.bin"))));
     }
 }
@@ -0,0 +1,313 @@
+//! Netlify style `_headers` and `_redirects` files for [`WebAssets`].
+//!
+//! [`WebAssets`]: ../web/struct.WebAssets.html
+use super::web::check_header;
+use proc_macro2::{Ident, Span};
+use std::fs;
+use std::path::Path;
+use utils;
+
+const HEADERS_FILE: &str = "_headers";
+const REDIRECTS_FILE: &str = "_redirects";
+
+#[derive(Debug, PartialEq)]
+struct HeaderRule {
+    pattern: String,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Redirect {
+    from: String,
+    to: String,
+    status: u16,
+    force: bool,
+}
+
+/// Whether the path is the `_headers` or `_redirects` file of the root.
+pub(super) fn is_config_file(root: &Path, path: &Path) -> bool {
+    path == root.join(HEADERS_FILE) || path == root.join(REDIRECTS_FILE)
+}
+
+/// Generates the header rule and redirect arrays from the files in the roots.
+///
+/// The rules of every root are concatenated in order.  Rewrites and not
+/// found rules to a local path without placeholders have to be to an asset,
+/// which `is_asset` checks.
+///
+/// # Panics
+///
+/// Panics if a file can't be read or is invalid, or if a rewrite or not found
+/// rule isn't to an asset.
+pub(super) fn generate<'a, R, F>(ident: &str, roots: R, is_asset: F) -> String
+where
+    R: IntoIterator<Item = &'a Path>,
+    F: Fn(&str) -> bool,
+{
+    let mut headers = Vec::new();
+    let mut redirects = Vec::new();
+    for root in roots {
+        headers.extend(read(&root.join(HEADERS_FILE), parse_headers));
+
+        let path = root.join(REDIRECTS_FILE);
+        let parsed = read(&path, parse_redirects);
+        if let Some(redirect) = parsed.iter().find(|r| !is_valid_target(r, &is_asset)) {
+            panic!(
+                "Invalid `{}`: `{}` is not an asset to serve with status {}",
+                path.display(),
+                redirect.to,
+                redirect.status
+            );
+        }
+        redirects.extend(parsed);
+    }
+
+    let headers_ident = Ident::new(&format!("{}_HEADER_RULES", ident), Span::call_site());
+    let headers_len = headers.len();
+    let headers = headers.iter().map(|rule| {
+        let pattern = &rule.pattern;
+        let names = rule.headers.iter().map(|(name, _)| name);
+        let values = rule.headers.iter().map(|(_, value)| value);
+        quote! {
+            ::includer::HeaderRule {
+                pattern: #pattern,
+                headers: &[#((#names, #values)),*],
+            }
+        }
+    });
+
+    let redirects_ident = Ident::new(&format!("{}_REDIRECTS", ident), Span::call_site());
+    let redirects_len = redirects.len();
+    let redirects = redirects.iter().map(|redirect| {
+        let Redirect {
+            from,
+            to,
+            status,
+            force,
+        } = redirect;
+        quote! {
+            ::includer::Redirect {
+                from: #from,
+                to: #to,
+                status: #status,
+                force: #force,
+            }
+        }
+    });
+
+    let tokens = quote! {
+        #[allow(dead_code)]
+        const #headers_ident: [::includer::HeaderRule; #headers_len] = [#(#headers),*];
+        #[allow(dead_code)]
+        const #redirects_ident: [::includer::Redirect; #redirects_len] = [#(#redirects),*];
+    };
+
+    format!("{}\n", tokens)
+}
+
+/// Whether a rewrite or not found rule is to an asset, other redirects are
+/// always valid.
+///
+/// Destinations with placeholders depend on the request, so only the ones
+/// without any are checked.
+fn is_valid_target<F: Fn(&str) -> bool>(redirect: &Redirect, is_asset: F) -> bool {
+    let rewrites = redirect.status == 200 || redirect.status == 404;
+    !rewrites || redirect.to.contains(':') || is_asset(&redirect.to)
+}
+
+fn read<T, F>(path: &Path, parse: F) -> Vec<T>
+where
+    F: Fn(&str) -> Result<Vec<T>, String>,
+{
+    if !path.is_file() {
+        return Vec::new();
+    }
+
+    utils::watch_path(path);
+    let source = fs::read_to_string(path).expect("Unable to read Netlify file");
+    match parse(&source) {
+        Ok(parsed) => parsed,
+        Err(e) => panic!("Invalid `{}`: {}", path.display(), e),
+    }
+}
+
+/// Lines that aren't empty or comments, with their line number.
+fn lines(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim_end()))
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+}
+
+fn check_pattern(pattern: &str) -> Result<(), String> {
+    if !pattern.starts_with('/') {
+        return Err(format!("`{}` is not an absolute path", pattern));
+    }
+
+    match pattern.find('*') {
+        Some(i) if i != pattern.len() - 1 || !pattern.ends_with("/*") => Err(format!(
+            "`{}` can only have a `*` as the last path segment",
+            pattern
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn parse_headers(source: &str) -> Result<Vec<HeaderRule>, String> {
+    let mut rules: Vec<HeaderRule> = Vec::new();
+
+    for (number, line) in lines(source) {
+        let error = |e: String| format!("line {}: {}", number, e);
+
+        if !line.starts_with(char::is_whitespace) {
+            check_pattern(line).map_err(error)?;
+            rules.push(HeaderRule {
+                pattern: line.to_string(),
+                headers: Vec::new(),
+            });
+            continue;
+        }
+
+        let rule = match rules.last_mut() {
+            Some(rule) => rule,
+            None => return Err(error("header without a path".to_string())),
+        };
+
+        let mut split = line.trim().splitn(2, ':');
+        let name = split.next().unwrap_or("").trim();
+        let value = match split.next() {
+            Some(value) => value.trim(),
+            None => {
+                return Err(error(format!(
+                    "`{}` is not a `Name: value` header",
+                    line.trim()
+                )))
+            }
+        };
+
+        check_header(name, value).map_err(error)?;
+        rule.headers.push((name.to_string(), value.to_string()));
+    }
+
+    Ok(rules)
+}
+
+fn parse_redirects(source: &str) -> Result<Vec<Redirect>, String> {
+    let mut redirects = Vec::new();
+
+    for (number, line) in lines(source) {
+        let error = |e: String| format!("line {}: {}", number, e);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        if fields.len() < 2 {
+            return Err(error("expected a path and a destination".to_string()));
+        }
+
+        let (from, to) = (fields[0], fields[1]);
+        check_pattern(from).map_err(error)?;
+
+        let external = to.starts_with("http://") || to.starts_with("https://");
+        if !external && !to.starts_with('/') {
+            return Err(error(format!(
+                "`{}` is not a destination, query parameters are not supported",
+                to
+            )));
+        }
+
+        let (status, force) = match fields.get(2) {
+            Some(status) if status.ends_with('!') => (&status[..status.len() - 1], true),
+            Some(status) => (*status, false),
+            None => ("301", false),
+        };
+
+        let status = match status.parse::<u16>() {
+            Ok(status @ 200) | Ok(status @ 404) if external => {
+                return Err(error(format!(
+                    "status {} needs a local destination",
+                    status
+                )))
+            }
+            Ok(status @ 200) | Ok(status @ 404) => status,
+            Ok(status @ 301..=303) | Ok(status @ 307..=308) => status,
+            _ => return Err(error(format!("`{}` is not a supported status", status))),
+        };
+
+        if fields.len() > 3 {
+            return Err(error("conditions are not supported".to_string()));
+        }
+
+        redirects.push(Redirect {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+            force,
+        });
+    }
+
+    Ok(redirects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let source = "# comment\n/*\n  X-Frame-Options: DENY\n\n/fonts/*\n  Access-Control-Allow-Origin: *\n";
+        assert_eq!(
+            parse_headers(source),
+            Ok(vec![
+                HeaderRule {
+                    pattern: "/*".to_string(),
+                    headers: vec![("X-Frame-Options".to_string(), "DENY".to_string())],
+                },
+                HeaderRule {
+                    pattern: "/fonts/*".to_string(),
+                    headers: vec![("Access-Control-Allow-Origin".to_string(), "*".to_string())],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(parse_headers("  X-Frame-Options: DENY").is_err());
+        assert!(parse_headers("/*\n  X-Frame-Options DENY").is_err());
+        assert!(parse_headers("/*/a\n  X-Frame-Options: DENY").is_err());
+    }
+
+    #[test]
+    fn redirects() {
+        let source =
+            "/old /new\n/blog/* https://blog.example.com/:splat 302!\n/* /index.html 200\n";
+        let redirects = parse_redirects(source).unwrap();
+        assert_eq!(redirects.len(), 3);
+        assert_eq!((redirects[0].status, redirects[0].force), (301, false));
+        assert_eq!((redirects[1].status, redirects[1].force), (302, true));
+        assert_eq!(redirects[2].to, "/index.html");
+    }
+
+    #[test]
+    fn rewrite_targets() {
+        let redirects = parse_redirects(
+            "/old /missing\n/* /index.html 200\n/docs/* /docs/:splat 200\n/a /missing 404\n",
+        )
+        .unwrap();
+        let is_asset = |uri: &str| uri == "/index.html";
+        let valid: Vec<bool> = redirects
+            .iter()
+            .map(|r| is_valid_target(r, is_asset))
+            .collect();
+        assert_eq!(valid, vec![true, true, true, false]);
+    }
+
+    #[test]
+    fn invalid_redirects() {
+        assert!(parse_redirects("/old").is_err());
+        assert!(parse_redirects("/store id=:id /blog/:id 301").is_err());
+        assert!(parse_redirects("/api/* https://api.example.com/:splat 200").is_err());
+        assert!(parse_redirects("/a /b 301 Country=us").is_err());
+        assert!(parse_redirects("/a /b 418").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Rewriting of the asset references inside of HTML and CSS files.
+use regex::Regex;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::LazyLock;
+
+static SCHEME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").unwrap());
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^'")\s]+))\s*\)"#).unwrap());
+static IMPORT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"@import\s+(?:"([^"]*)"|'([^']*)')"#).unwrap());
+static TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<([a-zA-Z][a-zA-Z0-9-]*)(\s[^>]*)>").unwrap());
+static ATTR: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)\s(src|srcset|poster|href|style)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+static REL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\srel\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#).unwrap());
+static RAW: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?is)<!--.*?-->|<script(?:\s[^>]*)?>(.*?)</script\s*>|<style(?:\s[^>]*)?>(.*?)</style\s*>",
+    )
+    .unwrap()
+});
+
+/// The `rel` values of `<link>` elements whose `href` is loaded as an asset.
+const LINK_RELS: [&str; 4] = ["stylesheet", "preload", "icon", "modulepreload"];
+
+/// The kind of file that references are rewritten in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Kind {
+    Html,
+    Css,
+}
+
+/// Returns the kind of the file from its extension, if it can have references.
+pub(super) fn kind(path: &Path) -> Option<Kind> {
+    match path.extension().and_then(OsStr::to_str) {
+        Some(ext) if ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm") => {
+            Some(Kind::Html)
+        }
+        Some(ext) if ext.eq_ignore_ascii_case("css") => Some(Kind::Css),
+        _ => None,
+    }
+}
+
+/// Replaces every local reference in the source with the result of `resolve`.
+///
+/// `resolve` is passed the reference without its query or fragment, which
+/// are kept as is.  References with a scheme, protocol relative references
+/// and fragment only references are left alone.  In HTML, the `src`,
+/// `srcset` and `poster` attributes, the `href` of `<link>` elements that
+/// load a stylesheet, an icon or a preload, and the CSS references of
+/// `<style>` elements and `style` attributes are rewritten.  Comments and the
+/// contents of `<script>` elements are never rewritten.  In CSS, `url()` and
+/// `@import` are rewritten.
+pub(super) fn rewrite<F>(source: &str, kind: Kind, mut resolve: F) -> Result<String, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut spans = match kind {
+        Kind::Html => html_references(source),
+        Kind::Css => css_references(source, 0),
+    };
+    spans.sort();
+
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+    for (start, end) in spans {
+        if start < last {
+            continue;
+        }
+
+        let reference = &source[start..end];
+        if !is_local(reference) {
+            continue;
+        }
+
+        let split = reference.find(['?', '#']).unwrap_or(reference.len());
+        let (path, suffix) = reference.split_at(split);
+        out.push_str(&source[last..start]);
+        out.push_str(&resolve(path)?);
+        out.push_str(suffix);
+        last = end;
+    }
+    out.push_str(&source[last..]);
+
+    Ok(out)
+}
+
+/// Resolves a reference found in the file at `source` to a file path.
+///
+/// Absolute references are relative to `root`, the directory the file was
+/// mounted from, and relative references are relative to the directory of the
+/// file.  Returns `None` for an absolute reference without a root.
+pub(super) fn resolve_path(root: Option<&Path>, source: &Path, reference: &str) -> Option<PathBuf> {
+    let (base, reference) = match reference.strip_prefix('/') {
+        Some(reference) => (root?.to_path_buf(), reference),
+        None => (source.parent()?.to_path_buf(), reference),
+    };
+
+    let mut path = base;
+    for component in Path::new(reference).components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(part) => path.push(part),
+            _ => {}
+        }
+    }
+
+    Some(path)
+}
+
+fn is_local(reference: &str) -> bool {
+    !(reference.is_empty()
+        || reference.starts_with('#')
+        || reference.starts_with("//")
+        || SCHEME.is_match(reference))
+}
+
+/// Returns the byte ranges of the `url()` and `@import` references, starting
+/// at `offset`.
+fn css_references(source: &str, offset: usize) -> Vec<(usize, usize)> {
+    URL.captures_iter(source)
+        .chain(IMPORT.captures_iter(source))
+        .filter_map(|c| c.iter().skip(1).flatten().next())
+        .map(|m| (offset + m.start(), offset + m.end()))
+        .collect()
+}
+
+/// Returns the byte ranges of the references in attributes of HTML elements
+/// and in `<style>` elements, outside of comments and `<script>` elements.
+fn html_references(source: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut skipped = Vec::new();
+    for raw in RAW.captures_iter(source) {
+        match (raw.get(1), raw.get(2)) {
+            (Some(script), _) => skipped.push(script.range()),
+            (_, Some(css)) => {
+                spans.extend(css_references(css.as_str(), css.start()));
+                skipped.push(css.range());
+            }
+            _ => skipped.push(raw.get(0).unwrap().range()),
+        }
+    }
+
+    for tag in TAG.captures_iter(source) {
+        let start = tag.get(0).unwrap().start();
+        if skipped.iter().any(|range| range.contains(&start)) {
+            continue;
+        }
+
+        let attrs = tag.get(2).unwrap();
+        let loads_href = tag[1].eq_ignore_ascii_case("link") && loads_href(attrs.as_str());
+        for attr in ATTR.captures_iter(attrs.as_str()) {
+            let name = attr[1].to_ascii_lowercase();
+            if name == "href" && !loads_href {
+                continue;
+            }
+
+            let value = attr.get(2).or_else(|| attr.get(3)).unwrap();
+            let start = attrs.start() + value.start();
+            if name == "srcset" {
+                spans.extend(srcset_urls(value.as_str(), start));
+            } else if name == "style" {
+                spans.extend(css_references(value.as_str(), start));
+            } else {
+                spans.push((start, start + value.len()));
+            }
+        }
+    }
+
+    spans
+}
+
+/// Whether the attributes of a `<link>` element have a `rel` that loads its
+/// `href` as an asset, rather than linking to another page.
+fn loads_href(attrs: &str) -> bool {
+    let rel = match REL.captures(attrs) {
+        Some(rel) => rel,
+        None => return false,
+    };
+
+    let value = rel
+        .iter()
+        .skip(1)
+        .flatten()
+        .next()
+        .map_or("", |m| m.as_str());
+    value
+        .split_ascii_whitespace()
+        .any(|rel| LINK_RELS.iter().any(|r| r.eq_ignore_ascii_case(rel)))
+}
+
+/// Returns the byte ranges of the urls in a `srcset` starting at `offset`.
+fn srcset_urls(srcset: &str, offset: usize) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut pos = 0;
+    for candidate in srcset.split(',') {
+        let trimmed = candidate.trim_start();
+        let start = offset + pos + candidate.len() - trimmed.len();
+        let len = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+        if len > 0 {
+            spans.push((start, start + len));
+        }
+        pos += candidate.len() + 1;
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upper(source: &str, kind: Kind) -> String {
+        rewrite(source, kind, |r| Ok(r.to_uppercase())).unwrap()
+    }
+
+    #[test]
+    fn rewrites_html() {
+        let html = r##"<link rel="stylesheet" href="style.css"><a href="/about">
+<img src='/img/a.png' srcset="a.png 1x, b.png 2x"><script src="https://cdn/x.js"></script>
+<div style="background: url(bg.png)"></div><a href="#top">
+<style>body { background: url('body.png') }</style><p>url(text.png)</p>
+<link rel="canonical" href="/about"><link rel="shortcut icon" href='favicon.ico'>
+<!-- <img src="old.png"> --><script>document.write("<img src='x.png'>")</script>"##;
+        assert_eq!(
+            upper(html, Kind::Html),
+            r##"<link rel="stylesheet" href="STYLE.CSS"><a href="/about">
+<img src='/IMG/A.PNG' srcset="A.PNG 1x, B.PNG 2x"><script src="https://cdn/x.js"></script>
+<div style="background: url(BG.PNG)"></div><a href="#top">
+<style>body { background: url('BODY.PNG') }</style><p>url(text.png)</p>
+<link rel="canonical" href="/about"><link rel="shortcut icon" href='FAVICON.ICO'>
+<!-- <img src="old.png"> --><script>document.write("<img src='x.png'>")</script>"##
+        );
+    }
+
+    #[test]
+    fn rewrites_css() {
+        let css = r##"@import "base.css"; body { background: url( "../bg.png?v=1#x" ) }
+.a { src: url(data:font/woff2;base64,AA) } .b { mask: url('#mask') }"##;
+        assert_eq!(
+            upper(css, Kind::Css),
+            r##"@import "BASE.CSS"; body { background: url( "../BG.PNG?v=1#x" ) }
+.a { src: url(data:font/woff2;base64,AA) } .b { mask: url('#mask') }"##
+        );
+    }
+
+    #[test]
+    fn resolve_paths() {
+        let root = Path::new("dist");
+        let source = Path::new("dist/css/style.css");
+        assert_eq!(
+            resolve_path(Some(root), source, "../img/bg.png"),
+            Some(PathBuf::from("dist/img/bg.png"))
+        );
+        assert_eq!(
+            resolve_path(Some(root), source, "/app.js"),
+            Some(PathBuf::from("dist/app.js"))
+        );
+        assert_eq!(
+            resolve_path(Some(root), source, "./font.woff2"),
+            Some(PathBuf::from("dist/css/font.woff2"))
+        );
+        assert_eq!(resolve_path(None, source, "/app.js"), None);
+    }
+}
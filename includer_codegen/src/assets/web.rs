@@ -1,35 +1,152 @@
+use super::compression::CompressionType;
+#[cfg(feature = "images")]
+use super::images;
+use super::netlify;
+use super::rewrite;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use minify;
 use proc_macro2::{Ident, Span};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::fmt;
+use std::fs;
+use std::iter;
+use std::mem;
 use std::path::{Path, PathBuf};
 use utils;
-use utils::Filter;
-use utils::Filter::*;
-use utils::FilterListType;
-use utils::FilterListType::*;
-use walkdir::WalkDir;
+use utils::{EmbedFile, Filter, FilterRule, Mount, Pack};
+use CollisionPolicy;
+use Entry;
 use Pipeline;
+use Transform;
 
-struct AssetInfo {
-    path: String,
-    clean_path: String,
-    has_gz: bool,
-    has_br: bool,
+pub use includer::TrailingSlash;
+
+/// The extensions and media types of sidecar files that are alternate
+/// representations of the file they are named after, such as `logo.png.webp`.
+const MEDIA_SIDECARS: [(&str, &str); 3] = [
+    ("avif", "image/avif"),
+    ("webp", "image/webp"),
+    ("jxl", "image/jxl"),
+];
+
+/// The hash algorithm of a Subresource Integrity digest.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SriAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+/// How a variant differs from the asset it's an alternate representation of.
+///
+/// Every property that isn't set is the same as the asset itself.
+///
+/// ```
+/// # use includer_codegen::prelude::*;
+/// #
+/// Variant::new().media_type("image/avif");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Variant {
+    media_type: Option<String>,
+    encoding: Option<String>,
+    language: Option<String>,
+}
+
+impl Variant {
+    pub fn new() -> Self {
+        Variant::default()
+    }
+
+    /// Sets the media type, such as `"image/webp"`.
+    pub fn media_type<S: Into<String>>(mut self, media_type: S) -> Self {
+        self.media_type = Some(media_type.into());
+        self
+    }
+
+    /// Sets the content coding, such as `"br"`.
+    pub fn encoding<S: Into<String>>(mut self, encoding: S) -> Self {
+        self.encoding = Some(encoding.into());
+        self
+    }
+
+    /// Sets the language tag, such as `"fr"`.
+    pub fn language<S: Into<String>>(mut self, language: S) -> Self {
+        self.language = Some(language.into());
+        self
+    }
 }
 
-enum CompressionType {
-    GZIP,
-    BROTLI,
+/// How [`WebAssets`] handles precompressed sidecar files that don't match
+/// the file they are a version of, such as an `app.js.gz` that wasn't
+/// rebuilt together with `app.js`.
+///
+/// A sidecar is stale if it doesn't decompress to exactly the bytes of the
+/// file.
+///
+/// [`WebAssets`]: ./struct.WebAssets.html
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SidecarPolicy {
+    /// Embed every sidecar without checking it.
+    Trust,
+
+    /// Panic with the paths of the stale sidecar and its file.
+    Error,
+
+    /// Leave the stale sidecar out and warn about it.
+    Warn,
+
+    /// Compress the file again instead of using the stale sidecar.
+    #[default]
+    Regenerate,
 }
 
 pub struct WebAssets {
     ident: String,
-    prefix: String,
-    path: PathBuf,
-    filters: Vec<Filter>,
-    filter_list_type: FilterListType,
+    mount: Mount,
+    mounts: Vec<Mount>,
+    overlay: CollisionPolicy,
+    files: Vec<Entry>,
+    aliases: Vec<(String, String)>,
     brotli: bool,
     gzip: bool,
+    zstd: bool,
+    deflate: bool,
+    #[cfg(feature = "compress")]
+    compress_rules: Vec<FilterRule>,
+    sidecars: SidecarPolicy,
+    index_files: Vec<String>,
+    trailing_slash: TrailingSlash,
+    strip_extensions: Vec<String>,
+    lowercase: bool,
+    cache_rules: Vec<(FilterRule, String)>,
+    cache_hashed: Option<String>,
+    cache_default: String,
+    header_rules: Vec<(FilterRule, String, String)>,
+    netlify: bool,
+    integrity_rules: Vec<(FilterRule, SriAlgorithm)>,
+    fingerprint_rules: Vec<FilterRule>,
+    rewrite_references: bool,
+    transforms: Vec<(FilterRule, Box<dyn Transform>)>,
+    texts: Vec<FilterRule>,
+    minify: bool,
+    #[cfg(feature = "images")]
+    optimize_images: bool,
+    #[cfg(feature = "images")]
+    webp_rules: Vec<FilterRule>,
+    #[cfg(feature = "images")]
+    responsive_rules: Vec<(FilterRule, Vec<u32>)>,
+    languages: Vec<String>,
+    default_language: Option<String>,
+    variant_rules: Vec<(FilterRule, Variant, Box<dyn Transform>)>,
+
+    /// The processed data of every file that was already processed, so that
+    /// fingerprinted files aren't processed again when they're embedded.
+    processed: RefCell<HashMap<PathBuf, Option<Vec<u8>>>>,
 }
 
 impl WebAssets {
@@ -39,12 +156,43 @@ impl WebAssets {
     pub fn new<S: Into<String>, P: Into<PathBuf>>(identifier: S, path: P) -> Self {
         WebAssets {
             ident: identifier.into(),
-            prefix: "/".to_string(),
-            path: path.into(),
-            filters: Vec::new(),
-            filter_list_type: Blacklist,
+            mount: Mount::new(path),
+            mounts: Vec::new(),
+            overlay: CollisionPolicy::LastWins,
+            files: Vec::new(),
+            aliases: Vec::new(),
             brotli: true,
             gzip: true,
+            zstd: false,
+            deflate: false,
+            #[cfg(feature = "compress")]
+            compress_rules: Vec::new(),
+            sidecars: SidecarPolicy::Regenerate,
+            index_files: vec!["index.html".to_string()],
+            trailing_slash: TrailingSlash::Never,
+            strip_extensions: Vec::new(),
+            lowercase: false,
+            cache_rules: Vec::new(),
+            cache_hashed: None,
+            cache_default: "no-cache".to_string(),
+            header_rules: Vec::new(),
+            netlify: false,
+            integrity_rules: Vec::new(),
+            fingerprint_rules: Vec::new(),
+            rewrite_references: false,
+            transforms: Vec::new(),
+            texts: Vec::new(),
+            minify: false,
+            #[cfg(feature = "images")]
+            optimize_images: false,
+            #[cfg(feature = "images")]
+            webp_rules: Vec::new(),
+            #[cfg(feature = "images")]
+            responsive_rules: Vec::new(),
+            languages: Vec::new(),
+            default_language: None,
+            variant_rules: Vec::new(),
+            processed: RefCell::new(HashMap::new()),
         }
     }
 
@@ -110,7 +258,7 @@ impl WebAssets {
     ///
     /// [`FilterRule::regex`]: ./utils/enum.FilterRule.html#method.regex
     pub fn filter(mut self, filter: Filter) -> Self {
-        self.filters.push(filter);
+        self.mount = self.mount.filter(filter);
         self
     }
 
@@ -122,25 +270,98 @@ impl WebAssets {
     ///
     /// Defaults to `"/"`
     pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
-        self.prefix = prefix.into();
+        self.mount = self.mount.prefix(prefix);
         self
     }
 
     /// Sets the path to the assets directory.
     pub fn set_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
-        self.path = path.into();
+        self.mount = self.mount.set_path(path);
         self
     }
 
     /// Set the filter list type to a blacklist.
     pub fn blacklist(mut self) -> Self {
-        self.filter_list_type = Blacklist;
+        self.mount = self.mount.blacklist();
         self
     }
 
     /// Set the filter list type to a whitelist.
     pub fn whitelist(mut self) -> Self {
-        self.filter_list_type = Whitelist;
+        self.mount = self.mount.whitelist();
+        self
+    }
+
+    /// Mounts another directory into the same asset set.
+    ///
+    /// The entries of every mount are merged into a single generated array,
+    /// the directory passed to [`new`] being the first mount.  See [`overlay`]
+    /// for what happens when mounts share a uri.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .mount(Mount::new("../branding").prefix("/branding"))
+    ///     .mount(Mount::new("../docs/book").prefix("/docs"));
+    /// ```
+    ///
+    /// [`new`]: #method.new
+    /// [`overlay`]: #method.overlay
+    pub fn mount(mut self, mount: Mount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+
+    /// Sets which entry is kept when multiple mounts have the same uri.
+    ///
+    /// Defaults to [`CollisionPolicy::LastWins`] so that later mounts overlay
+    /// the earlier ones.
+    ///
+    /// [`CollisionPolicy::LastWins`]: ./enum.CollisionPolicy.html#variant.LastWins
+    pub fn overlay(mut self, policy: CollisionPolicy) -> Self {
+        self.overlay = policy;
+        self
+    }
+
+    /// Adds a single file at an explicit uri.
+    ///
+    /// The file doesn't need to be inside of any mounted directory, and it
+    /// overlays mounted entries with the same uri like a mount would.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist").add_file("../LICENSE", "/LICENSE");
+    /// ```
+    pub fn add_file<P: Into<PathBuf>, S: Into<String>>(mut self, path: P, uri: S) -> Self {
+        self.files.push(Entry {
+            uri: uri.into(),
+            path: path.into(),
+            logical_uri: None,
+        });
+        self
+    }
+
+    /// Makes the asset at `to_uri` also available at `from_uri`.
+    ///
+    /// Both entries share the same embedded data, it is not included twice.
+    /// The `to_uri` of a [fingerprinted] asset is its uri without the hash,
+    /// while the alias itself isn't fingerprinted.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist").alias("/favicon.ico", "/static/favicon.ico");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if no entry has the uri `to_uri`.
+    ///
+    /// [fingerprinted]: #method.fingerprint
+    pub fn alias<S: Into<String>, T: Into<String>>(mut self, from_uri: S, to_uri: T) -> Self {
+        self.aliases.push((from_uri.into(), to_uri.into()));
         self
     }
 
@@ -162,159 +383,1689 @@ impl WebAssets {
         self.gzip = gzip;
         self
     }
-}
-
-impl fmt::Display for WebAssets {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut entries = Vec::new();
-        for maybe_entry in WalkDir::new(&self.path) {
-            let entry = maybe_entry.unwrap();
-
-            // We don't have special rules for directories, but we can't use
-            // walkdir's entry filter because we don't want files under
-            // directories to be skipped.
-            if entry.file_type().is_dir() {
-                utils::watch_path(entry.path());
-                continue;
-            }
-
-            if self.filters.is_empty() {
-                match self.filter_list_type {
-                    Whitelist => break,
-                    Blacklist => {
-                        if !skip_compressed(&self, entry.path().extension()) {
-                            utils::watch_path(entry.path());
-                            entries.push(PathBuf::from(entry.path()));
-                        }
-                        continue;
-                    }
-                }
-            }
-
-            let mut matched = true;
-            for filter in &self.filters {
-                // Skip all filters that don't match the entry
-                if !filter.matches(entry.path()) {
-                    continue;
-                }
-
-                if skip_compressed(&self, entry.path().extension()) {
-                    matched = false;
-                }
 
-                if let Exclude(_) = filter {
-                    matched = false;
-                }
+    /// Sets whether to include the zstd version of every file too
+    ///
+    /// The zstd version of `app.js` is `app.js.zst`.  Defaults to `false`.
+    pub fn zstd(mut self, zstd: bool) -> Self {
+        self.zstd = zstd;
+        self
+    }
 
-                break;
-            }
+    /// Sets whether to include the deflate version of every file too
+    ///
+    /// The deflate version of `app.js` is `app.js.zz`, in the zlib format
+    /// that the `deflate` content coding uses.  Defaults to `false`, as only
+    /// old clients prefer it over gzip.
+    pub fn deflate(mut self, deflate: bool) -> Self {
+        self.deflate = deflate;
+        self
+    }
 
-            if matched {
-                utils::watch_path(entry.path());
-                entries.push(PathBuf::from(entry.path()));
-            }
-        }
+    /// Compresses every asset matching the rule at build time.
+    ///
+    /// Assets are compressed with every enabled content coding that they
+    /// don't have a precompressed version in, keeping the compressed version
+    /// only if it's smaller.  Processed assets are compressed after they are
+    /// processed.  Requires the `compress` cargo feature.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .compress(FilterRule::regex(r"\.(html|css|js|svg)$"))
+    ///     .deflate(true);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if an asset can't be compressed.
+    #[cfg(feature = "compress")]
+    pub fn compress(mut self, rule: FilterRule) -> Self {
+        self.compress_rules.push(rule);
+        self
+    }
 
-        let asset_info: Vec<AssetInfo> = entries
-            .iter()
-            .map(|p| AssetInfo {
-                path: utils::path_to_string(p),
-                clean_path: normalize_path(p, &self.path, &self.prefix),
-                has_gz: compressed_exists(&p, CompressionType::GZIP),
-                has_br: compressed_exists(&p, CompressionType::BROTLI),
-            }).collect();
+    /// Sets how precompressed sidecar files that are stale are handled.
+    ///
+    /// Every sidecar is decompressed and compared with its file, unless the
+    /// policy is [`SidecarPolicy::Trust`].  Defaults to
+    /// [`SidecarPolicy::Regenerate`].
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist").sidecars(SidecarPolicy::Error);
+    /// ```
+    ///
+    /// [`SidecarPolicy::Trust`]: ./enum.SidecarPolicy.html#variant.Trust
+    /// [`SidecarPolicy::Regenerate`]: ./enum.SidecarPolicy.html#variant.Regenerate
+    pub fn sidecars(mut self, policy: SidecarPolicy) -> Self {
+        self.sidecars = policy;
+        self
+    }
 
-        let code = generate_asset_const(&self.ident, asset_info);
-        write!(f, "{}", code)
+    /// Sets the file names that collapse into the uri of their directory.
+    ///
+    /// Defaults to only `"index.html"`.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist").index_files(["index.htm", "default.html"]);
+    /// ```
+    pub fn index_files<I, S>(mut self, files: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.index_files = files.into_iter().map(|f| f.as_ref().to_string()).collect();
+        self
     }
-}
 
-impl Pipeline for WebAssets {}
+    /// Sets whether directory uris end with a slash.
+    ///
+    /// The root directory is always `"/"`.  Defaults to [`TrailingSlash::Never`].
+    ///
+    /// [`TrailingSlash::Never`]: ./enum.TrailingSlash.html#variant.Never
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> Self {
+        self.trailing_slash = trailing_slash;
+        self
+    }
 
-fn compressed_exists(path: &Path, compression: CompressionType) -> bool {
-    let ext = match compression {
-        CompressionType::GZIP => ".gz",
-        CompressionType::BROTLI => ".br",
-    };
+    /// Strips an extension from file uris, for "clean" urls.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// // "/about.html" is served at "/about"
+    /// WebAssets::new("ASSETS", "../web/dist").strip_extension("html");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the extension has a leading period.
+    pub fn strip_extension<S: Into<String>>(mut self, extension: S) -> Self {
+        let ext = extension.into();
 
-    let f = path.file_name().unwrap();
-    let new_f = format!("{}{}", f.to_str().unwrap(), ext);
-    let mut p = PathBuf::from(path);
-    p.set_file_name(new_f);
-    Path::exists(&p)
-}
+        if ext.starts_with('.') {
+            panic!("Stripped extensions should not contain a period prefix!");
+        }
 
-fn skip_compressed(builder: &WebAssets, ext: Option<&OsStr>) -> bool {
-    if builder.gzip && ext == Some("gz".as_ref()) {
-        return true;
+        self.strip_extensions.push(ext);
+        self
     }
 
-    if builder.brotli && ext == Some("br".as_ref()) {
-        return true;
+    /// Sets whether uris are lowercased.
+    ///
+    /// Defaults to `false`.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
     }
 
-    false
-}
-
-fn normalize_path(path: &Path, dir: &Path, prefix: &str) -> String {
-    let path = path.strip_prefix(&dir).unwrap();
-    let path = PathBuf::from("/").join(prefix).join(&path);
+    /// Sets the `Cache-Control` value of every asset matching the rule.
+    ///
+    /// Rules are applied in the order that they were added, the first
+    /// matching rule determines the value.  Rules take precedence over
+    /// [`cache_control_hashed`] and [`default_cache_control`].
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .cache_control(FilterRule::extension("woff2"), "public, max-age=604800")
+    ///     .cache_control(FilterRule::extension("html"), "no-cache");
+    /// ```
+    ///
+    /// [`cache_control_hashed`]: #method.cache_control_hashed
+    /// [`default_cache_control`]: #method.default_cache_control
+    pub fn cache_control<S: Into<String>>(mut self, rule: FilterRule, value: S) -> Self {
+        self.cache_rules.push((rule, value.into()));
+        self
+    }
 
-    if path.file_name().unwrap() == "index.html" {
-        path.parent().unwrap().to_str().unwrap().to_owned()
-    } else {
-        path.to_str().unwrap().to_owned()
+    /// Sets the `Cache-Control` value of assets with a hash in their file name.
+    ///
+    /// A file name has a hash when any of its period or dash separated parts
+    /// (apart from the extension) are at least 6 lowercase hexadecimal
+    /// characters with both a digit and a letter, such as `main.3f9a1c.js` or
+    /// `7b7482c9c13eb09e3029.js`.  Assets fingerprinted by [`fingerprint`] are
+    /// always hashed.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .cache_control_hashed("public, max-age=31536000, immutable");
+    /// ```
+    ///
+    /// [`fingerprint`]: #method.fingerprint
+    pub fn cache_control_hashed<S: Into<String>>(mut self, value: S) -> Self {
+        self.cache_hashed = Some(value.into());
+        self
     }
-}
 
-fn generate_asset_const(ident_str: &str, raw_assets: Vec<AssetInfo>) -> String {
-    let len = raw_assets.len();
-    let mut structs = Vec::new();
+    /// Sets the `Cache-Control` value of assets that don't match any rule.
+    ///
+    /// Defaults to `"no-cache"`.
+    pub fn default_cache_control<S: Into<String>>(mut self, value: S) -> Self {
+        self.cache_default = value.into();
+        self
+    }
 
-    for AssetInfo {
-        path,
-        clean_path,
-        has_gz,
-        has_br,
-    } in raw_assets
+    /// Adds a response header to every asset matching the rule.
+    ///
+    /// Unlike [`cache_control`], every matching rule applies, in the order
+    /// that they were added.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .header(FilterRule::extension("html"), "Content-Security-Policy", "default-src 'self'")
+    ///     .header(FilterRule::extension("woff2"), "Access-Control-Allow-Origin", "*")
+    ///     .header(FilterRule::regex(r"downloads/"), "Content-Disposition", "attachment");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name isn't a valid header name, or the value contains
+    /// characters that aren't allowed in a header value.
+    ///
+    /// ```should_panic
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// // should panic
+    /// WebAssets::new("ASSETS", "../web/dist").header(FilterRule::extension("html"), "X Frame", "DENY");
+    /// ```
+    ///
+    /// [`cache_control`]: #method.cache_control
+    pub fn header<N, V>(mut self, rule: FilterRule, name: N, value: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
     {
-        let gz = if has_gz {
-            let path_gz = path.clone() + ".gz";
-            quote! {Some(include_bytes!(#path_gz))}
-        } else {
-            quote! {None}
-        };
+        let (name, value) = (name.into(), value.into());
+        if let Err(e) = check_header(&name, &value) {
+            panic!("{}", e);
+        }
+        self.header_rules.push((rule, name, value));
+        self
+    }
 
-        let br = if has_br {
-            let path_br = path.clone() + ".br";
-            quote! {Some(include_bytes!(#path_br))}
-        } else {
-            quote! {None}
-        };
+    /// Sets whether to use Netlify style `_headers` and `_redirects` files.
+    ///
+    /// The files are read from the root of every mount and are not embedded
+    /// as assets themselves.  Instead, they are validated and generated as
+    /// `[HeaderRule; _]` and `[Redirect; _]` arrays named after the identifier
+    /// with a `_HEADER_RULES` and `_REDIRECTS` suffix, to use with the
+    /// `includer::Resolver`.  The rules of all mounts are concatenated in
+    /// mount order, and missing files generate empty arrays.
+    ///
+    /// Rewrites and not found rules, with a status of 200 or 404, have to be
+    /// to an embedded asset unless their destination has placeholders.
+    ///
+    /// Proxying, conditions and query parameters in `_redirects` are not
+    /// supported.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if either file is invalid, or if a
+    /// rewrite or not found rule isn't to an embedded asset.
+    pub fn netlify(mut self, netlify: bool) -> Self {
+        self.netlify = netlify;
+        self
+    }
 
-        structs.push(quote! {
-            WebAsset {
-            uri: #clean_path,
-            data: include_bytes!(#path),
-            data_gz: #gz,
-            data_br: #br,
-            }
-        });
+    /// Computes a Subresource Integrity digest for every asset matching the rule.
+    ///
+    /// Rules are applied in the order that they were added, the first
+    /// matching rule determines the algorithm.  The digest is available as
+    /// the `integrity` of the asset, and through a generated function named
+    /// after the lowercased identifier with an `_integrity` suffix.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .integrity(FilterRule::extension("js"), SriAlgorithm::Sha384)
+    ///     .integrity(FilterRule::extension("css"), SriAlgorithm::Sha384);
+    /// ```
+    ///
+    /// With the identifier `ASSETS`, the generated function can be used as:
+    ///
+    /// ```ignore
+    /// let integrity: Option<&'static str> = assets_integrity("/app.js");
+    /// ```
+    pub fn integrity(mut self, rule: FilterRule, algorithm: SriAlgorithm) -> Self {
+        self.integrity_rules.push((rule, algorithm));
+        self
     }
 
-    let ident = Ident::new(ident_str, Span::call_site());
+    /// Adds a hash of the contents to the uri of every asset matching the rule.
+    ///
+    /// The hash is inserted before the extension, `/app.js` becomes something
+    /// like `/app.4c1e2d3f.js`.  Index files are never fingerprinted, and
+    /// fingerprinted assets always use the [`cache_control_hashed`] value if
+    /// it is set.
+    ///
+    /// A manifest function named after the lowercased identifier with a `_url`
+    /// suffix is generated, mapping the uri before fingerprinting to the final
+    /// uri of every asset.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/src")
+    ///     .fingerprint(FilterRule::extension("js"))
+    ///     .fingerprint(FilterRule::extension("css"))
+    ///     .cache_control_hashed("public, max-age=31536000, immutable");
+    /// ```
+    ///
+    /// With the identifier `ASSETS`, the generated function can be used as:
+    ///
+    /// ```ignore
+    /// let url: Option<&'static str> = assets_url("/app.js");
+    /// ```
+    ///
+    /// [`cache_control_hashed`]: #method.cache_control_hashed
+    pub fn fingerprint(mut self, rule: FilterRule) -> Self {
+        self.fingerprint_rules.push(rule);
+        self
+    }
 
-    let tokens = quote! {
-        const #ident: [WebAsset; #len] = [#(#structs),*];
-    };
+    /// Sets whether references inside of HTML and CSS assets are rewritten.
+    ///
+    /// References are written relative to the assets directory, before any
+    /// prefix or fingerprint is applied.  Every reference to a local file is
+    /// rewritten to the final uri of that asset, so `<script src="app.js">`
+    /// becomes something like `<script src="/static/app.4c1e2d3f.js">`.  The
+    /// rewritten files are written into `OUT_DIR` and embedded instead of the
+    /// originals, without their precompressed versions.
+    ///
+    /// In HTML, the `src`, `srcset` and `poster` attributes, the `href` of
+    /// `<link>` elements with a `stylesheet`, `preload`, `modulepreload` or
+    /// `icon` rel, and CSS `url()` references are rewritten.  Links of `<a>`
+    /// elements and other `<link>` elements, such as canonical links, are left
+    /// alone as they usually point at routes.  Comments and scripts are never
+    /// rewritten.  In CSS, `url()` and `@import` references are rewritten.
+    ///
+    /// Fingerprints of rewritten files are computed after rewriting, so they
+    /// change when any asset they reference changes.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .prefix("/static")
+    ///     .fingerprint(FilterRule::regex(r"\.(js|css|png)$"))
+    ///     .rewrite_references(true);
+    /// ```
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a file references a local file that
+    /// isn't an asset, or if fingerprinted files reference each other in a
+    /// cycle.
+    pub fn rewrite_references(mut self, rewrite: bool) -> Self {
+        self.rewrite_references = rewrite;
+        self
+    }
 
-    format!("{}\n", tokens)
-}
+    /// Transforms every file matching the rule before embedding it.
+    ///
+    /// Every matching transform applies, in the order that they were added,
+    /// and before references are rewritten.  The output is written into
+    /// `OUT_DIR` and embedded instead of the file, without its precompressed
+    /// versions.  Integrity digests and fingerprints are computed from the
+    /// output.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// # use std::path::Path;
+    /// #
+    /// let version = |_: &Path, data: Vec<u8>| -> Result<Vec<u8>, String> {
+    ///     let config = String::from_utf8(data).map_err(|e| e.to_string())?;
+    ///     Ok(config.replace("__VERSION__", env!("CARGO_PKG_VERSION")).into_bytes())
+    /// };
+    ///
+    /// WebAssets::new("ASSETS", "../web/dist").transform(FilterRule::regex(r"config\.js$"), version);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a transform fails.
+    pub fn transform<T: Transform + 'static>(mut self, rule: FilterRule, transform: T) -> Self {
+        self.transforms.push((rule, Box::new(transform)));
+        self
+    }
+
+    /// Embeds every file matching the rule as text, available from
+    /// `WebAsset::text`.
+    ///
+    /// The files are validated as UTF-8, after they're transformed and
+    /// minified, so that they can be borrowed as strings without checking them
+    /// at runtime.  Their variants are embedded as usual.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist").text(FilterRule::extension("html"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a matching file isn't valid UTF-8.
+    pub fn text(mut self, rule: FilterRule) -> Self {
+        self.texts.push(rule);
+        self
+    }
+
+    /// Sets whether to minify HTML, CSS, javascript and JSON assets.
+    ///
+    /// Only the formats whose cargo feature is enabled are minified, see the
+    /// [`minify`] module, and a cargo warning is emitted when none of them
+    /// are.  Minifying happens after the transforms and after
+    /// references are rewritten.  Like transforms, the output is embedded
+    /// without the precompressed versions of the file.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/src").minify(true);
+    /// ```
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a file can't be minified, such as
+    /// when it has a syntax error.
+    ///
+    /// [`minify`]: ./minify/index.html
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.minify = minify;
+        self
+    }
+
+    /// Sets whether PNG images are recompressed losslessly.
+    ///
+    /// The recompressed image is only embedded if it's smaller, and like
+    /// transforms, without the precompressed versions of the file.  Otherwise
+    /// the file is embedded as is, along with its sidecars.  Requires the
+    /// `images` cargo feature.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "images")]
+    pub fn optimize_images(mut self, optimize: bool) -> Self {
+        self.optimize_images = optimize;
+        self
+    }
+
+    /// Generates a lossless WebP variant of every PNG and JPEG image matching
+    /// the rule.
+    ///
+    /// The variant is added to the `variants` of the asset if it's smaller
+    /// than the image, and served by `WebAsset::response` to clients that
+    /// accept `image/webp`.  A `.webp` sidecar file takes precedence over the
+    /// generated variant.  Requires the `images` cargo feature.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist").webp(FilterRule::regex(r"^img/"));
+    /// ```
+    #[cfg(feature = "images")]
+    pub fn webp(mut self, rule: FilterRule) -> Self {
+        self.webp_rules.push(rule);
+        self
+    }
+
+    /// Adds resized copies of every PNG and JPEG image matching the rule.
+    ///
+    /// A copy is added for every width smaller than the width of the image,
+    /// keeping its aspect ratio, at the uri of the image with a `-{width}w`
+    /// suffix before the extension.  `/img/hero.jpg` that is 1600 pixels wide
+    /// with the widths `[640, 1280, 1920]` adds `/img/hero-640w.jpg` and
+    /// `/img/hero-1280w.jpg`, to use in a `srcset`.  The copies are written
+    /// into `OUT_DIR` with the same file name, which is what the other rules
+    /// are matched against.
+    ///
+    /// Rules are applied in the order that they were added, the first
+    /// matching rule determines the widths.  Requires the `images` cargo
+    /// feature.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .responsive(FilterRule::extension("jpg"), [640, 1280, 1920])
+    ///     .webp(FilterRule::extension("jpg"));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if an image can't be decoded.
+    #[cfg(feature = "images")]
+    pub fn responsive<I: IntoIterator<Item = u32>>(mut self, rule: FilterRule, widths: I) -> Self {
+        self.responsive_rules
+            .push((rule, widths.into_iter().collect()));
+        self
+    }
+
+    /// Sets the language tags of files that are translations of other files.
+    ///
+    /// A file with a tag before its extension is a language variant of the
+    /// file without it, if that file exists.  With the language `fr`,
+    /// `README.fr.md` is added to the `variants` of `README.md` instead of
+    /// being an asset of its own, and served by `WebAsset::response` to
+    /// clients that prefer French.  Language variants are processed like the
+    /// asset, and get their own precompressed versions.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// #
+    /// WebAssets::new("ASSETS", "../web/dist")
+    ///     .languages(["fr", "de"])
+    ///     .default_language("en");
+    /// ```
+    pub fn languages<I, S>(mut self, languages: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.languages = languages
+            .into_iter()
+            .map(|l| l.as_ref().to_string())
+            .collect();
+        self
+    }
+
+    /// Sets the language of assets that have language variants.
+    ///
+    /// Without it, clients that don't prefer any of the variants are served
+    /// the asset itself without a `Content-Language`.
+    pub fn default_language<S: Into<String>>(mut self, language: S) -> Self {
+        self.default_language = Some(language.into());
+        self
+    }
+
+    /// Adds a variant produced by a transform to every asset matching the rule.
+    ///
+    /// The transform is passed the embedded data of the asset, after it was
+    /// processed, and its output is embedded as a variant described by
+    /// `variant`.  Every matching rule adds a variant, after the variants
+    /// from sidecar files.
+    ///
+    /// ```
+    /// # use includer_codegen::prelude::*;
+    /// # use std::path::Path;
+    /// #
+    /// let uppercase = |_: &Path, data: Vec<u8>| -> Result<Vec<u8>, String> {
+    ///     Ok(data.to_ascii_uppercase())
+    /// };
+    ///
+    /// WebAssets::new("ASSETS", "../notes").variant(
+    ///     FilterRule::extension("txt"),
+    ///     Variant::new().language("x-shouting"),
+    ///     uppercase,
+    /// );
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Generating the pipeline panics if a transform fails.
+    pub fn variant<T>(mut self, rule: FilterRule, variant: Variant, transform: T) -> Self
+    where
+        T: Transform + 'static,
+    {
+        self.variant_rules
+            .push((rule, variant, Box::new(transform)));
+        self
+    }
+}
+
+impl fmt::Display for WebAssets {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.generate(&self.entries()))
+    }
+}
+
+impl Pipeline for WebAssets {
+    fn entries(&self) -> Vec<Entry> {
+        let mounts = iter::once(&self.mount).chain(&self.mounts).map(|mount| {
+            mount
+                .entries()
+                .into_iter()
+                .filter(|e| !is_variant_file(self, &e.path))
+                .filter(|e| !(self.netlify && netlify::is_config_file(mount.path(), &e.path)))
+                .map(|e| Entry {
+                    uri: normalize_uri(self, &e.uri),
+                    path: e.path,
+                    logical_uri: None,
+                })
+                .collect()
+        });
+
+        let mut mounts: Vec<Vec<Entry>> = mounts.collect();
+        mounts.push(utils::watch_files(&self.files));
+
+        let mut entries = utils::merge_mounts(mounts, self.overlay);
+        #[cfg(feature = "images")]
+        {
+            entries = add_resized(self, entries);
+        }
+
+        let uris: HashMap<PathBuf, String> = entries
+            .iter()
+            .rev()
+            .map(|e| (e.path.clone(), e.uri.clone()))
+            .collect();
+        let mut hashes = HashMap::new();
+        for entry in &mut entries {
+            if fingerprinted(self, &entry.path) {
+                let hash = content_hash(self, &entry.path, &uris, &mut hashes, &mut Vec::new());
+                let uri = fingerprint_uri(&entry.uri, &hash);
+                entry.logical_uri = Some(mem::replace(&mut entry.uri, uri));
+            }
+        }
+
+        utils::add_aliases(&mut entries, &self.aliases);
+        entries
+    }
+
+    fn generate(&self, entries: &[Entry]) -> String {
+        generate(self, entries, None)
+    }
+
+    fn generate_packed(&self, entries: &[Entry], pack: &mut Pack) -> String {
+        generate(self, entries, Some(pack))
+    }
+}
+
+fn generate(builder: &WebAssets, entries: &[Entry], pack: Option<&mut Pack>) -> String {
+    let minifiers = cfg!(any(
+        feature = "minify-html",
+        feature = "minify-css",
+        feature = "minify-js",
+        feature = "minify-json"
+    ));
+    if builder.minify && !minifiers {
+        println!(
+            "cargo:warning={} is set to minify, but no `minify-*` feature is enabled",
+            builder.ident
+        );
+    }
+
+    let (data, variants): (Vec<_>, Vec<_>) = embedded(builder, entries).into_iter().unzip();
+    let (paths, _) = utils::unique_paths(entries);
+    let integrities: Vec<_> = paths
+        .iter()
+        .zip(&data)
+        .map(|(path, data)| integrity(builder, path, data))
+        .collect();
+
+    let mut code = generate_asset_const(builder, entries, &data, &variants, &integrities, pack);
+    code += &generate_normalization_const(builder);
+    if !builder.integrity_rules.is_empty() {
+        code += &generate_integrity_fn(builder, entries, &integrities);
+    }
+
+    if !builder.fingerprint_rules.is_empty() {
+        code += &generate_manifest_fn(builder, entries);
+    }
+
+    if builder.netlify {
+        let roots = iter::once(&builder.mount).chain(&builder.mounts);
+        let uris: HashSet<&str> = entries.iter().map(|e| e.uri.as_str()).collect();
+        code += &netlify::generate(&builder.ident, roots.map(Mount::path), |to| {
+            is_asset(builder, &uris, to)
+        });
+    }
+
+    code
+}
+
+/// Appends an extension to the file name, `logo.png` becomes `logo.png.webp`.
+fn sidecar(path: &Path, ext: &str) -> PathBuf {
+    let f = path.file_name().unwrap();
+    let new_f = format!("{}.{}", f.to_str().unwrap(), ext);
+    let mut p = PathBuf::from(path);
+    p.set_file_name(new_f);
+    p
+}
+
+fn compressed_exists(path: &Path, compression: CompressionType) -> bool {
+    Path::exists(&sidecar(path, compression.extension()))
+}
+
+fn compression_enabled(builder: &WebAssets, compression: CompressionType) -> bool {
+    match compression {
+        CompressionType::Gzip => builder.gzip,
+        CompressionType::Brotli => builder.brotli,
+        CompressionType::Zstd => builder.zstd,
+        CompressionType::Deflate => builder.deflate,
+    }
+}
+
+/// Whether the file is a variant of another file instead of an asset.
+fn is_variant_file(builder: &WebAssets, path: &Path) -> bool {
+    let ext = path.extension();
+    if skip_compressed(builder, ext) {
+        return true;
+    }
+
+    let is_media = MEDIA_SIDECARS
+        .iter()
+        .any(|(sidecar, _)| ext == Some(sidecar.as_ref()));
+    if is_media && path.with_extension("").is_file() {
+        return true;
+    }
+
+    language_base(builder, path).is_some()
+}
+
+/// Returns the language of a language variant, and the file it's a variant of.
+fn language_base<'a>(builder: &'a WebAssets, path: &Path) -> Option<(&'a str, PathBuf)> {
+    let stem = path.file_stem()?.to_str()?;
+    let dot = stem.rfind('.').filter(|&dot| dot > 0)?;
+    let language = builder
+        .languages
+        .iter()
+        .find(|l| l.as_str() == &stem[dot + 1..])?;
+
+    let base = match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}.{}", &stem[..dot], ext.to_str()?)),
+        None => path.with_file_name(&stem[..dot]),
+    };
+    if base.is_file() {
+        Some((language, base))
+    } else {
+        None
+    }
+}
+
+/// Returns the path of the language variant of the file.
+fn language_path(path: &Path, language: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    match path.extension().and_then(OsStr::to_str) {
+        Some(ext) => path.with_file_name(format!("{}.{}.{}", stem, language, ext)),
+        None => path.with_file_name(format!("{}.{}", stem, language)),
+    }
+}
+
+fn skip_compressed(builder: &WebAssets, ext: Option<&OsStr>) -> bool {
+    CompressionType::ALL.iter().any(|&compression| {
+        compression_enabled(builder, compression) && ext == Some(compression.extension().as_ref())
+    })
+}
+
+/// Normalizes a uri the same way as `includer::Normalization` does.
+fn normalize_uri(builder: &WebAssets, uri: &str) -> String {
+    includer::normalize(
+        uri,
+        &builder.index_files,
+        builder.trailing_slash,
+        &builder.strip_extensions,
+        builder.lowercase,
+    )
+}
+
+/// Whether the path finds an asset the same way as `includer::Normalization`
+/// does.
+fn is_asset(builder: &WebAssets, uris: &HashSet<&str>, path: &str) -> bool {
+    let uri = normalize_uri(builder, path);
+    match builder.trailing_slash {
+        TrailingSlash::Always if !uri.ends_with('/') => {
+            uris.contains(uri.as_str()) || uris.contains(format!("{}/", uri).as_str())
+        }
+        _ => uris.contains(uri.as_str()),
+    }
+}
+
+fn cache_control<'a>(builder: &'a WebAssets, path: &Path) -> &'a str {
+    let rule = builder
+        .cache_rules
+        .iter()
+        .find(|(rule, _)| rule.matches(path));
+    if let Some((_, value)) = rule {
+        return value;
+    }
+
+    match builder.cache_hashed {
+        Some(ref value) if is_hashed(path) || fingerprinted(builder, path) => value,
+        _ => &builder.cache_default,
+    }
+}
+
+fn headers<'a>(builder: &'a WebAssets, path: &Path) -> Vec<(&'a str, &'a str)> {
+    builder
+        .header_rules
+        .iter()
+        .filter(|(rule, _, _)| rule.matches(path))
+        .map(|(_, name, value)| (name.as_str(), value.as_str()))
+        .collect()
+}
+
+pub(super) fn check_header(name: &str, value: &str) -> Result<(), String> {
+    let token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(token) {
+        return Err(format!("`{}` is not a valid header name", name));
+    }
+
+    if value
+        .chars()
+        .any(|c| c != '\t' && (c.is_control() || !c.is_ascii()))
+    {
+        return Err(format!(
+            "`{}` is not a valid value for header `{}`",
+            value, name
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the integrity of the embedded data if the file matches an integrity rule.
+fn integrity(builder: &WebAssets, path: &Path, data: &Path) -> Option<String> {
+    let rule = builder
+        .integrity_rules
+        .iter()
+        .find(|(rule, _)| rule.matches(path));
+    let algorithm = match rule {
+        Some((_, algorithm)) => algorithm,
+        None => return None,
+    };
+
+    let data = fs::read(data).expect("Unable to read asset for its integrity");
+    let (name, digest) = match algorithm {
+        SriAlgorithm::Sha256 => ("sha256", Sha256::digest(&data).to_vec()),
+        SriAlgorithm::Sha384 => ("sha384", Sha384::digest(&data).to_vec()),
+        SriAlgorithm::Sha512 => ("sha512", Sha512::digest(&data).to_vec()),
+    };
+
+    Some(format!("{}-{}", name, BASE64.encode(digest)))
+}
+
+/// Whether the file matches a fingerprint rule, index files never do.
+fn fingerprinted(builder: &WebAssets, path: &Path) -> bool {
+    let file_name = path.file_name().and_then(OsStr::to_str);
+    builder
+        .fingerprint_rules
+        .iter()
+        .any(|rule| rule.matches(path))
+        && !builder
+            .index_files
+            .iter()
+            .any(|f| Some(f.as_str()) == file_name)
+}
+
+fn hash(data: &[u8]) -> String {
+    let hash = format!("{:x}", Sha256::digest(data));
+    hash[..8].to_string()
+}
+
+/// Returns the fingerprint hash of the file, after processing it.
+///
+/// `uris` are the uris of every file before fingerprinting, and `visiting` the
+/// fingerprinted files whose references are currently being resolved.
+fn content_hash(
+    builder: &WebAssets,
+    path: &Path,
+    uris: &HashMap<PathBuf, String>,
+    hashes: &mut HashMap<PathBuf, String>,
+    visiting: &mut Vec<PathBuf>,
+) -> String {
+    if let Some(hash) = hashes.get(path) {
+        return hash.clone();
+    }
+
+    if visiting.iter().any(|p| p == path) {
+        panic!(
+            "`{}` is part of a reference cycle between fingerprinted files",
+            path.display()
+        );
+    }
+
+    visiting.push(path.to_path_buf());
+    let processed = processed_once(builder, path, |target| {
+        let uri = uris.get(target)?;
+        if fingerprinted(builder, target) {
+            let hash = content_hash(builder, target, uris, hashes, visiting);
+            Some(fingerprint_uri(uri, &hash))
+        } else {
+            Some(uri.clone())
+        }
+    });
+    visiting.pop();
+
+    let data = processed
+        .unwrap_or_else(|| fs::read(path).expect("Unable to read asset for its fingerprint"));
+    let hash = hash(&data);
+    hashes.insert(path.to_path_buf(), hash.clone());
+    hash
+}
+
+/// Returns the processed contents of the file like [`processed`], only
+/// processing it the first time.
+///
+/// [`processed`]: fn.processed.html
+fn processed_once<F>(builder: &WebAssets, path: &Path, uri_of: F) -> Option<Vec<u8>>
+where
+    F: FnMut(&Path) -> Option<String>,
+{
+    if let Some(data) = builder.processed.borrow().get(path) {
+        return data.clone();
+    }
+
+    let data = processed(builder, path, uri_of);
+    builder
+        .processed
+        .borrow_mut()
+        .insert(path.to_path_buf(), data.clone());
+    data
+}
+
+/// Returns the contents of the file after its transforms, rewriting its
+/// references and minifying it, if it's processed at all.
+///
+/// `uri_of` returns the final uri of the asset at a path, if there is one.
+fn processed<F>(builder: &WebAssets, path: &Path, uri_of: F) -> Option<Vec<u8>>
+where
+    F: FnMut(&Path) -> Option<String>,
+{
+    let read = || fs::read(path).expect("Unable to read asset to process it");
+    let mut data = utils::transform(path, &builder.transforms);
+
+    if let Some(kind) = rewrite::kind(path).filter(|_| builder.rewrite_references) {
+        let source = data.take().unwrap_or_else(read);
+        data = Some(rewrite_references(builder, path, kind, source, uri_of));
+    }
+
+    if let Some(minifier) = minify::for_path(path).filter(|_| builder.minify) {
+        let source = data.take().unwrap_or_else(read);
+        match minifier.transform(path, source) {
+            Ok(minified) => data = Some(minified),
+            Err(e) => panic!("Unable to minify `{}`: {}", path.display(), e),
+        }
+    }
+
+    #[cfg(feature = "images")]
+    {
+        if builder.optimize_images && images::is_png(path) {
+            // unchanged data is embedded from the file itself, with its sidecars
+            let (source, processed) = match data.take() {
+                Some(data) => (data, true),
+                None => (read(), false),
+            };
+            match images::recompress(&source) {
+                Ok(Some(recompressed)) => data = Some(recompressed),
+                Ok(None) => data = Some(source).filter(|_| processed),
+                Err(e) => panic!("Unable to recompress `{}`: {}", path.display(), e),
+            }
+        }
+    }
+
+    data
+}
+
+/// Adds the resized copies of every image matching a responsive rule right
+/// after the image.
+#[cfg(feature = "images")]
+fn add_resized(builder: &WebAssets, entries: Vec<Entry>) -> Vec<Entry> {
+    let mut all = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let rule = builder
+            .responsive_rules
+            .iter()
+            .find(|(rule, _)| rule.matches(&entry.path));
+        let resized = match rule {
+            Some((_, widths)) if images::is_image(&entry.path) => {
+                images::resized(&entry.path, widths)
+            }
+            _ => Vec::new(),
+        };
+
+        let uri = entry.uri.clone();
+        all.push(entry);
+        all.extend(resized.into_iter().map(|(width, path)| Entry {
+            uri: images::resized_uri(&uri, width),
+            path,
+            logical_uri: None,
+        }));
+    }
+
+    all
+}
+
+/// Returns the path of the WebP variant of the embedded data, if it has one.
+#[cfg(feature = "images")]
+fn webp_path(builder: &WebAssets, path: &Path, data_path: &Path) -> Option<PathBuf> {
+    if !images::is_image(path) || !builder.webp_rules.iter().any(|rule| rule.matches(path)) {
+        return None;
+    }
+
+    let data = fs::read(data_path).expect("Unable to read image to convert it");
+    match images::webp(&data) {
+        Ok(webp) => webp.map(|webp| utils::write_out_dir(&webp)),
+        Err(e) => panic!("Unable to convert `{}` to WebP: {}", path.display(), e),
+    }
+}
+
+#[cfg(not(feature = "images"))]
+fn webp_path(_: &WebAssets, _: &Path, _: &Path) -> Option<PathBuf> {
+    None
+}
+
+fn rewrite_references<F>(
+    builder: &WebAssets,
+    path: &Path,
+    kind: rewrite::Kind,
+    source: Vec<u8>,
+    mut uri_of: F,
+) -> Vec<u8>
+where
+    F: FnMut(&Path) -> Option<String>,
+{
+    let source = String::from_utf8(source).unwrap_or_else(|_| {
+        panic!(
+            "Unable to rewrite the references of `{}`, it isn't UTF-8",
+            path.display()
+        )
+    });
+    let root = iter::once(&builder.mount)
+        .chain(&builder.mounts)
+        .map(Mount::path)
+        .find(|root| path.starts_with(root));
+
+    let result = rewrite::rewrite(&source, kind, |reference| {
+        let target = rewrite::resolve_path(root, path, reference);
+        let index_files = builder.index_files.iter();
+        target
+            .iter()
+            .cloned()
+            .chain(index_files.filter_map(|f| target.as_ref().map(|t| t.join(f))))
+            .find_map(|target| uri_of(&target))
+            .ok_or_else(|| {
+                format!(
+                    "`{}` references `{}` which isn't an asset",
+                    path.display(),
+                    reference
+                )
+            })
+    });
+
+    match result {
+        Ok(rewritten) => rewritten.into_bytes(),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Returns the path of the data to embed for every unique path of the
+/// entries, along with its variants.
+///
+/// The data is the file itself, unless it was processed.
+fn embedded(builder: &WebAssets, entries: &[Entry]) -> Vec<(PathBuf, Vec<(Variant, PathBuf)>)> {
+    let mut uris = HashMap::new();
+    for entry in entries {
+        uris.entry(entry.path.as_path()).or_insert(&entry.uri);
+    }
+    let uri_of = |target: &Path| uris.get(target).map(|u| u.to_string());
+
+    let (paths, _) = utils::unique_paths(entries);
+    paths
+        .iter()
+        .map(|path| {
+            let data = match processed_once(builder, path, uri_of) {
+                Some(data) => utils::write_out_dir(&data),
+                None => path.to_path_buf(),
+            };
+            let variants = variants(builder, path, &data, uri_of);
+            (data, variants)
+        })
+        .collect()
+}
+
+/// Returns the variants of the file and the paths of their data.
+///
+/// These are its precompressed versions, media type sidecar files, generated
+/// WebP images, the output of variant transforms and its language variants,
+/// in that order.
+fn variants<F>(
+    builder: &WebAssets,
+    path: &Path,
+    data_path: &Path,
+    uri_of: F,
+) -> Vec<(Variant, PathBuf)>
+where
+    F: Fn(&Path) -> Option<String>,
+{
+    let mut variants = compressed(builder, path, data_path, Variant::new());
+
+    for (ext, media_type) in MEDIA_SIDECARS.iter() {
+        let sidecar = sidecar(path, ext);
+        if sidecar.is_file() {
+            variants.push((Variant::new().media_type(*media_type), sidecar));
+        }
+    }
+
+    if !sidecar(path, "webp").is_file() {
+        if let Some(webp) = webp_path(builder, path, data_path) {
+            variants.push((Variant::new().media_type("image/webp"), webp));
+        }
+    }
+
+    for (rule, variant, transform) in &builder.variant_rules {
+        if !rule.matches(path) {
+            continue;
+        }
+
+        let data = fs::read(data_path).expect("Unable to read asset for its variant");
+        match transform.transform(path, data) {
+            Ok(data) => variants.push((variant.clone(), utils::write_out_dir(&data))),
+            Err(e) => panic!("Unable to transform `{}`: {}", path.display(), e),
+        }
+    }
+
+    for language in &builder.languages {
+        let file = language_path(path, language);
+        if !file.is_file() {
+            continue;
+        }
+
+        let data = match processed(builder, &file, &uri_of) {
+            Some(data) => utils::write_out_dir(&data),
+            None => file.clone(),
+        };
+        let variant = Variant::new().language(language.as_str());
+        variants.extend(compressed(builder, &file, &data, variant.clone()));
+        variants.push((variant, data));
+    }
+
+    variants
+}
+
+/// Returns the enabled precompressed versions of the data as variants.
+///
+/// Sidecar files are checked according to the sidecar policy, and versions
+/// without a sidecar file are compressed at build time if the file matches a
+/// compress rule.
+fn compressed(
+    builder: &WebAssets,
+    path: &Path,
+    data_path: &Path,
+    base: Variant,
+) -> Vec<(Variant, PathBuf)> {
+    // the data is read once, and only if it's compressed or checked
+    let mut data = None;
+    let mut variants = Vec::new();
+    for &compression in CompressionType::ALL.iter() {
+        if !compression_enabled(builder, compression) {
+            continue;
+        }
+
+        let variant = base.clone().encoding(compression.encoding());
+        if !compressed_exists(data_path, compression) {
+            if compresses(builder, path) {
+                let data = source(&mut data, data_path);
+                variants.extend(compressed_data(path, data, compression).map(|c| (variant, c)));
+            }
+            continue;
+        }
+
+        let sidecar = sidecar(data_path, compression.extension());
+        if builder.sidecars == SidecarPolicy::Trust
+            || !is_stale(source(&mut data, data_path), &sidecar, compression)
+        {
+            variants.push((variant, sidecar));
+            continue;
+        }
+
+        match builder.sidecars {
+            SidecarPolicy::Trust => unreachable!(),
+            SidecarPolicy::Error => panic!(
+                "`{}` is stale, it doesn't match `{}`",
+                sidecar.display(),
+                data_path.display()
+            ),
+            SidecarPolicy::Warn => {
+                println!("cargo:warning=stale `{}` was left out", sidecar.display());
+            }
+            SidecarPolicy::Regenerate => {
+                let data = source(&mut data, data_path);
+                variants.extend(compressed_data(path, data, compression).map(|c| (variant, c)));
+            }
+        }
+    }
+
+    variants
+}
+
+/// Returns the data of the file, reading it on first use.
+fn source<'a>(data: &'a mut Option<Vec<u8>>, data_path: &Path) -> &'a [u8] {
+    data.get_or_insert_with(|| fs::read(data_path).expect("Unable to read asset to compress it"))
+}
+
+/// Whether the sidecar doesn't decompress to the data.
+fn is_stale(data: &[u8], sidecar: &Path, compression: CompressionType) -> bool {
+    let compressed = fs::read(sidecar).expect("Unable to read sidecar to check it");
+    compression
+        .decompress(&compressed)
+        .map_or(true, |decompressed| decompressed != data)
+}
+
+/// Whether the file matches a compress rule.
+#[cfg(feature = "compress")]
+fn compresses(builder: &WebAssets, path: &Path) -> bool {
+    builder.compress_rules.iter().any(|rule| rule.matches(path))
+}
+
+#[cfg(not(feature = "compress"))]
+fn compresses(_: &WebAssets, _: &Path) -> bool {
+    false
+}
+
+/// Returns the path of the compressed data, if it's smaller than the data.
+fn compressed_data(path: &Path, data: &[u8], compression: CompressionType) -> Option<PathBuf> {
+    match compression.compress(data.to_vec()) {
+        Ok(compressed) if compressed.len() < data.len() => Some(utils::write_out_dir(&compressed)),
+        Ok(_) => None,
+        Err(e) => panic!("Unable to compress `{}`: {}", path.display(), e),
+    }
+}
+
+/// Inserts the hash before the extension of the last uri segment.
+fn fingerprint_uri(uri: &str, hash: &str) -> String {
+    let split = uri.rfind('/').map_or(0, |i| i + 1);
+    let (dir, file) = uri.split_at(split);
+    match file.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}{}.{}{}", dir, &file[..dot], hash, &file[dot..]),
+        _ => format!("{}{}.{}", dir, file, hash),
+    }
+}
+
+fn is_hashed(path: &Path) -> bool {
+    let stem = match path.file_stem().and_then(OsStr::to_str) {
+        Some(stem) => stem,
+        None => return false,
+    };
+
+    stem.split(['.', '-']).any(|part| {
+        part.len() >= 6
+            && part.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+            && part.chars().any(|c| c.is_ascii_digit())
+            && part.chars().any(|c| c.is_ascii_alphabetic())
+    })
+}
+
+fn generate_asset_const(
+    builder: &WebAssets,
+    entries: &[Entry],
+    data_paths: &[PathBuf],
+    variants: &[Vec<(Variant, PathBuf)>],
+    integrities: &[Option<String>],
+    pack: Option<&mut Pack>,
+) -> String {
+    let (paths, indices) = utils::unique_paths(entries);
+    let mut media_types = Vec::new();
+    let mut cache = Vec::new();
+    let mut headers = Vec::new();
+    let mut languages = Vec::new();
+    let mut variant_lists = Vec::new();
+
+    // the data of every path is followed by the data of its variants
+    let files: Vec<_> = paths
+        .iter()
+        .zip(data_paths.iter().zip(variants))
+        .flat_map(|(path, (data, variants))| {
+            let text = builder.texts.iter().any(|rule| rule.matches(path));
+            iter::once(EmbedFile {
+                text,
+                ..EmbedFile::new(path, data)
+            })
+            .chain(
+                variants
+                    .iter()
+                    .map(move |(_, variant)| EmbedFile::new(path, variant)),
+            )
+        })
+        .collect();
+    let embedded = utils::embed(&files, pack);
+    let data = &embedded.items;
+
+    let mut blob_indices = embedded.indices.iter().zip(&files);
+    let mut data_refs = Vec::new();
+    let mut texts = Vec::new();
+    for (i, raw_path) in paths.iter().enumerate() {
+        let (&k, file) = blob_indices.next().unwrap();
+        data_refs.push(&embedded.refs[k]);
+        texts.push(match embedded.texts[k] {
+            Some(ref text) if file.text => quote! {Some(#text)},
+            _ => quote! {None},
+        });
+
+        let mut list = Vec::new();
+        for (variant, _) in &variants[i] {
+            let data_ref = &embedded.refs[*blob_indices.next().unwrap().0];
+            let media_type = option(&variant.media_type);
+            let encoding = option(&variant.encoding);
+            let language = option(&variant.language);
+            list.push(quote! {
+                ::includer::Variant {
+                    media_type: #media_type,
+                    encoding: #encoding,
+                    language: #language,
+                    data: #data_ref,
+                }
+            });
+        }
+
+        let has_languages = variants[i].iter().any(|(v, _)| v.language.is_some());
+        languages.push(if has_languages {
+            option(&builder.default_language)
+        } else {
+            quote! {None}
+        });
+        variant_lists.push(list);
+        media_types.push(media_type(raw_path));
+        cache.push(cache_control(builder, raw_path));
+        headers.push(self::headers(builder, raw_path));
+    }
+
+    let mut structs = Vec::new();
+    for (Entry { uri, .. }, i) in entries.iter().zip(indices) {
+        let data_ref = data_refs[i];
+        let text = &texts[i];
+        let media_type = &media_types[i];
+        let cache_control = cache[i];
+        let names = headers[i].iter().map(|(name, _)| name);
+        let values = headers[i].iter().map(|(_, value)| value);
+        let integrity = match integrities[i] {
+            Some(ref integrity) => quote! {Some(#integrity)},
+            None => quote! {None},
+        };
+        let language = &languages[i];
+        let variants = &variant_lists[i];
+        // the data of external packs isn't constant, so their variants aren't
+        // promoted to statics
+        let variants = if embedded.external && !variants.is_empty() {
+            quote! {::std::boxed::Box::leak(::std::boxed::Box::new([#(#variants),*]))}
+        } else {
+            quote! {&[#(#variants),*]}
+        };
+        structs.push(quote! {
+            ::includer::WebAsset {
+                uri: #uri,
+                data: #data_ref,
+                text: #text,
+                media_type: #media_type,
+                cache_control: #cache_control,
+                headers: &[#((#names, #values)),*],
+                integrity: #integrity,
+                language: #language,
+                variants: #variants,
+            }
+        });
+    }
+
+    let tokens = utils::asset_array(
+        &builder.ident,
+        "WebAsset",
+        data,
+        &structs,
+        embedded.external,
+    );
+
+    format!("{}\n", tokens)
+}
+
+/// Returns the media type of the file from its extension, or
+/// `application/octet-stream` if it's unknown.
+fn media_type(path: &Path) -> String {
+    let media_type = mime_guess::from_path(path).first_or_octet_stream();
+    media_type.essence_str().to_string()
+}
+
+fn option(value: &Option<String>) -> proc_macro2::TokenStream {
+    match value {
+        Some(value) => quote! {Some(#value)},
+        None => quote! {None},
+    }
+}
+
+/// Generates the integrity lookup from the digests computed for every unique
+/// path, so that the files aren't read and hashed again.
+fn generate_integrity_fn(
+    builder: &WebAssets,
+    entries: &[Entry],
+    integrities: &[Option<String>],
+) -> String {
+    let ident = Ident::new(
+        &format!("{}_integrity", builder.ident.to_lowercase()),
+        Span::call_site(),
+    );
+
+    let (_, indices) = utils::unique_paths(entries);
+    let (uris, integrities): (Vec<_>, Vec<_>) = entries
+        .iter()
+        .zip(indices)
+        .filter_map(|(e, i)| integrities[i].as_ref().map(|integrity| (&e.uri, integrity)))
+        .unzip();
+
+    let len = uris.len();
+
+    let tokens = quote! {
+        #[allow(dead_code)]
+        fn #ident(uri: &str) -> Option<&'static str> {
+            const INTEGRITIES: [(&str, &str); #len] = [#((#uris, #integrities)),*];
+            INTEGRITIES.iter().find(|(u, _)| *u == uri).map(|(_, i)| *i)
+        }
+    };
+
+    format!("{}\n", tokens)
+}
+
+fn generate_manifest_fn(builder: &WebAssets, entries: &[Entry]) -> String {
+    let ident = Ident::new(
+        &format!("{}_url", builder.ident.to_lowercase()),
+        Span::call_site(),
+    );
+
+    let (logical, uris): (Vec<_>, Vec<_>) =
+        entries.iter().map(|e| (e.logical_uri(), &e.uri)).unzip();
+    let len = uris.len();
+
+    let tokens = quote! {
+        #[allow(dead_code)]
+        fn #ident(uri: &str) -> Option<&'static str> {
+            const MANIFEST: [(&str, &str); #len] = [#((#logical, #uris)),*];
+            MANIFEST.iter().find(|(l, _)| *l == uri).map(|(_, u)| *u)
+        }
+    };
+
+    format!("{}\n", tokens)
+}
+
+fn generate_normalization_const(builder: &WebAssets) -> String {
+    let ident = Ident::new(
+        &format!("{}_NORMALIZATION", builder.ident),
+        Span::call_site(),
+    );
+    let index_files = &builder.index_files;
+    let strip_extensions = &builder.strip_extensions;
+    let lowercase = builder.lowercase;
+    let trailing_slash = match builder.trailing_slash {
+        TrailingSlash::Always => quote! {::includer::TrailingSlash::Always},
+        TrailingSlash::Never => quote! {::includer::TrailingSlash::Never},
+    };
+
+    let tokens = quote! {
+        #[allow(dead_code)]
+        const #ident: ::includer::Normalization = ::includer::Normalization {
+            index_files: &[#(#index_files),*],
+            trailing_slash: #trailing_slash,
+            strip_extensions: &[#(#strip_extensions),*],
+            lowercase: #lowercase,
+        };
+    };
+
+    format!("{}\n", tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn hashed_file_names() {
+        assert!(is_hashed(Path::new("dist/main.3f9a1c.js")));
+        assert!(is_hashed(Path::new("dist/main.3f9a1c7e.js")));
+        assert!(is_hashed(Path::new("dist/7b7482c9c13eb09e3029.js")));
+        assert!(is_hashed(Path::new("dist/vendor-0a1b2c3d.css")));
+        assert!(!is_hashed(Path::new("dist/facade.js")));
+        assert!(!is_hashed(Path::new("dist/index.html")));
+        assert!(!is_hashed(Path::new("dist/report-202401.pdf")));
+        assert!(!is_hashed(Path::new("dist/report-20240131.pdf")));
+        assert!(!is_hashed(Path::new("dist/v1.0.12ab.js")));
+        assert!(!is_hashed(Path::new("dist/main.3f9a1.js")));
+        assert!(!is_hashed(Path::new("dist/DEADBEEF1.js")));
+    }
+
+    #[test]
+    fn media_types() {
+        assert_eq!(media_type(Path::new("dist/css/site.css")), "text/css");
+        assert_eq!(media_type(Path::new("dist/index.HTML")), "text/html");
+        assert_eq!(
+            media_type(Path::new("dist/LICENSE")),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn cache_control_precedence() {
+        let builder = WebAssets::new("ASSETS", "dist")
+            .cache_control(FilterRule::extension("html"), "no-store")
+            .cache_control_hashed("immutable")
+            .default_cache_control("max-age=60");
+        // explicit rules take precedence over hashed file names
+        assert!(is_hashed(Path::new("dist/a1b2c3.html")));
+        assert_eq!(
+            cache_control(&builder, Path::new("dist/a1b2c3.html")),
+            "no-store"
+        );
+        assert_eq!(
+            cache_control(&builder, Path::new("dist/main.3f9a1c.js")),
+            "immutable"
+        );
+        assert_eq!(
+            cache_control(&builder, Path::new("dist/main.js")),
+            "max-age=60"
+        );
+    }
+
+    #[test]
+    fn integrity_digest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("app.js");
+        fs::write(&path, "alert('Hello, world.');").unwrap();
+
+        let builder = WebAssets::new("ASSETS", dir)
+            .integrity(FilterRule::extension("js"), SriAlgorithm::Sha384);
+        assert_eq!(
+            integrity(&builder, &path, &path).unwrap(),
+            "sha384-H8BRh8j48O9oYatfu5AZzq6A9RINhZO5H16dQZngK7T62em8MUt1FLm52t+eX6xO"
+        );
+        let index = dir.join("index.html");
+        assert_eq!(integrity(&builder, &index, &index), None);
+    }
+
+    #[test]
+    fn variant_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for file in &[
+            "README.md",
+            "README.fr.md",
+            "README.de.md",
+            "logo.png",
+            "logo.png.webp",
+        ] {
+            fs::write(dir.join(file), file).unwrap();
+        }
+        fs::write(dir.join("photo.webp"), "photo").unwrap();
+
+        let builder = WebAssets::new("ASSETS", dir)
+            .languages(["fr"])
+            .gzip(false)
+            .brotli(false);
+        assert!(is_variant_file(&builder, &dir.join("README.fr.md")));
+        assert!(is_variant_file(&builder, &dir.join("logo.png.webp")));
+        assert!(!is_variant_file(&builder, &dir.join("README.de.md")));
+        assert!(!is_variant_file(&builder, &dir.join("photo.webp")));
+
+        let readme = dir.join("README.md");
+        assert_eq!(
+            variants(&builder, &readme, &readme, |_| None),
+            vec![(Variant::new().language("fr"), dir.join("README.fr.md"))]
+        );
+        let logo = dir.join("logo.png");
+        assert_eq!(
+            variants(&builder, &logo, &logo, |_| None),
+            vec![(
+                Variant::new().media_type("image/webp"),
+                dir.join("logo.png.webp")
+            )]
+        );
+    }
+
+    #[test]
+    fn stale_sidecars() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("app.js");
+        fs::write(&path, "alert('Hello, world.');").unwrap();
+        let gz = dir.join("app.js.gz");
+        let current = CompressionType::Gzip
+            .compress(fs::read(&path).unwrap())
+            .unwrap();
+        fs::write(&gz, current).unwrap();
+
+        // older sidecars are fine as long as they match
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        fs::File::options()
+            .write(true)
+            .open(&gz)
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+        let data = fs::read(&path).unwrap();
+        assert!(!is_stale(&data, &gz, CompressionType::Gzip));
+
+        fs::write(&gz, "outdated").unwrap();
+        assert!(is_stale(&data, &gz, CompressionType::Gzip));
+        assert_eq!(
+            WebAssets::new("ASSETS", dir).sidecars,
+            SidecarPolicy::Regenerate
+        );
+        let trusted = WebAssets::new("ASSETS", dir).sidecars(SidecarPolicy::Trust);
+        assert_eq!(
+            compressed(&trusted, &path, &path, Variant::new()),
+            vec![(Variant::new().encoding("gzip"), gz.clone())]
+        );
+        let checked = WebAssets::new("ASSETS", dir).sidecars(SidecarPolicy::Warn);
+        assert!(compressed(&checked, &path, &path, Variant::new()).is_empty());
+    }
+
+    #[test]
+    fn fingerprint_uris() {
+        assert_eq!(fingerprint_uri("/app.js", "4c1e2d3f"), "/app.4c1e2d3f.js");
+        assert_eq!(
+            fingerprint_uri("/js/app.min.js", "4c1e2d3f"),
+            "/js/app.min.4c1e2d3f.js"
+        );
+        assert_eq!(
+            fingerprint_uri("/v1.0/LICENSE", "4c1e2d3f"),
+            "/v1.0/LICENSE.4c1e2d3f"
+        );
+        assert_eq!(
+            fingerprint_uri("/.htaccess", "4c1e2d3f"),
+            "/.htaccess.4c1e2d3f"
+        );
+    }
+
+    #[test]
+    fn fingerprinted_aliases() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("app.js"), "alert('Hello, world.');").unwrap();
+
+        let builder = WebAssets::new("ASSETS", dir)
+            .fingerprint(FilterRule::extension("js"))
+            .alias("/main.js", "/app.js")
+            .gzip(false)
+            .brotli(false);
+        let entries = builder.entries();
+        assert_eq!(entries.len(), 2);
+        assert!(is_hashed(Path::new(&entries[0].uri)));
+        assert_eq!(entries[0].logical_uri(), "/app.js");
+        assert_eq!(entries[1].uri, "/main.js");
+        assert_eq!(entries[1].path, entries[0].path);
+    }
+
+    #[test]
+    fn fingerprinted_files_are_processed_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("app.js"), "alert('Hello, world.');").unwrap();
+
+        let runs = Rc::new(Cell::new(0));
+        let counted = runs.clone();
+        let builder = WebAssets::new("ASSETS", dir)
+            .fingerprint(FilterRule::extension("js"))
+            .transform(FilterRule::extension("js"), move |_: &Path, data| {
+                counted.set(counted.get() + 1);
+                Ok(data)
+            });
+        let entries = builder.entries();
+        assert_eq!(runs.get(), 1);
+
+        let data = processed_once(&builder, &entries[0].path, |_| None);
+        assert_eq!(data.unwrap(), b"alert('Hello, world.');");
+        assert_eq!(runs.get(), 1);
+    }
+
+    #[test]
+    fn headers_all_apply() {
+        let builder = WebAssets::new("ASSETS", "dist")
+            .header(FilterRule::extension("html"), "X-Frame-Options", "DENY")
+            .header(
+                FilterRule::regex("docs"),
+                "Content-Disposition",
+                "attachment",
+            )
+            .header(FilterRule::extension("js"), "X-Ignored", "true");
+        assert_eq!(
+            headers(&builder, Path::new("dist/docs/index.html")),
+            vec![
+                ("X-Frame-Options", "DENY"),
+                ("Content-Disposition", "attachment")
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        assert!(check_header("X Test", "value").is_err());
+        assert!(check_header("X-Test", "line\nbreak").is_err());
+    }
+
+    #[test]
+    fn normalize_default() {
+        let builder = WebAssets::new("ASSETS", "dist");
+        assert_eq!(normalize_uri(&builder, "/index.html"), "/");
+        assert_eq!(normalize_uri(&builder, "/docs/index.html"), "/docs");
+        assert_eq!(normalize_uri(&builder, "/About.html"), "/About.html");
+    }
 
-#[cfg(test)]
-mod tests {
     #[test]
-    fn it_works() {
-        assert_eq!(1 + 1, 2);
+    fn normalize_clean() {
+        let builder = WebAssets::new("ASSETS", "dist")
+            .index_files(["index.html", "default.htm"])
+            .trailing_slash(TrailingSlash::Always)
+            .strip_extension("html")
+            .lowercase(true);
+        assert_eq!(normalize_uri(&builder, "/index.html"), "/");
+        assert_eq!(normalize_uri(&builder, "/Docs/Default.htm"), "/docs/");
+        assert_eq!(normalize_uri(&builder, "/About.html"), "/about");
+        assert_eq!(normalize_uri(&builder, "/.html"), "/.html");
     }
 }
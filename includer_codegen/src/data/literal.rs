@@ -0,0 +1,476 @@
+//! Writing of serializable values as Rust expressions.
+use proc_macro2::Literal;
+use serde::ser::{self, Serialize};
+use std::error;
+use std::fmt;
+
+/// Returns a Rust expression that creates the value.
+pub(super) fn to_expr<T: Serialize>(value: &T) -> Result<String, Error> {
+    value.serialize(Expr)
+}
+
+/// Why a value can't be written as an expression.
+#[derive(Debug)]
+pub(super) struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// Returns the name as an identifier, a raw one if it's a keyword.
+fn ident(name: &str) -> Result<String, Error> {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do",
+        "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let",
+        "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+        "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+        "virtual", "where", "while", "yield",
+    ];
+
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name != "_"
+        && !["self", "Self", "super", "crate"].contains(&name);
+    if !valid {
+        return Err(Error(format!(
+            "`{}` is not a Rust identifier, renamed fields and variants aren't supported",
+            name
+        )));
+    }
+
+    Ok(if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    })
+}
+
+fn from(value: String) -> String {
+    format!("::std::convert::From::from({})", value)
+}
+
+fn float(value: f64, ty: &str) -> String {
+    if value.is_nan() {
+        format!("::std::{}::NAN", ty)
+    } else if value.is_infinite() && value > 0.0 {
+        format!("::std::{}::INFINITY", ty)
+    } else if value.is_infinite() {
+        format!("::std::{}::NEG_INFINITY", ty)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+/// A serializer into a Rust expression.
+struct Expr;
+
+/// The kind of value that a [`Compound`] is.
+enum Kind {
+    Seq,
+    Tuple,
+    TupleStruct(String),
+    Map,
+    Struct(String),
+}
+
+/// A value with fields or elements, which is written once it ends.
+struct Compound {
+    kind: Kind,
+    items: Vec<String>,
+    key: Option<String>,
+}
+
+impl Compound {
+    fn new(kind: Kind) -> Self {
+        Compound {
+            kind,
+            items: Vec::new(),
+            key: None,
+        }
+    }
+
+    fn element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.items.push(value.serialize(Expr)?);
+        Ok(())
+    }
+
+    fn field<T: ?Sized + Serialize>(&mut self, name: &str, value: &T) -> Result<(), Error> {
+        let value = value.serialize(Expr)?;
+        self.items.push(format!("{}: {}", ident(name)?, value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<String, Error> {
+        let items = self.items.join(", ");
+        Ok(match self.kind {
+            Kind::Seq | Kind::Map => from(format!("[{}]", items)),
+            Kind::Tuple if self.items.len() == 1 => format!("({},)", items),
+            Kind::Tuple => format!("({})", items),
+            Kind::TupleStruct(name) => format!("{}({})", name, items),
+            Kind::Struct(name) => format!("{} {{ {} }}", name, items),
+        })
+    }
+}
+
+impl ser::Serializer for Expr {
+    type Ok = String;
+    type Error = Error;
+    type SerializeSeq = Compound;
+    type SerializeTuple = Compound;
+    type SerializeTupleStruct = Compound;
+    type SerializeTupleVariant = Compound;
+    type SerializeMap = Compound;
+    type SerializeStruct = Compound;
+    type SerializeStructVariant = Compound;
+
+    fn serialize_bool(self, v: bool) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<String, Error> {
+        if v.is_finite() {
+            Ok(format!("{:?}", v))
+        } else {
+            Ok(float(f64::from(v), "f32"))
+        }
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<String, Error> {
+        Ok(float(v, "f64"))
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, Error> {
+        Ok(format!("{:?}", v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, Error> {
+        Ok(from(format!("{:?}", v)))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<String, Error> {
+        Ok(from(format!("&{}[..]", Literal::byte_string(v))))
+    }
+
+    fn serialize_none(self) -> Result<String, Error> {
+        Ok("None".to_string())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<String, Error> {
+        Ok(format!("Some({})", value.serialize(Expr)?))
+    }
+
+    fn serialize_unit(self) -> Result<String, Error> {
+        Ok("()".to_string())
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<String, Error> {
+        ident(name)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<String, Error> {
+        Ok(format!("{}::{}", ident(name)?, ident(variant)?))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<String, Error> {
+        Ok(format!("{}({})", ident(name)?, value.serialize(Expr)?))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<String, Error> {
+        let value = value.serialize(Expr)?;
+        Ok(format!("{}::{}({})", ident(name)?, ident(variant)?, value))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound, Error> {
+        Ok(Compound::new(Kind::Seq))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Kind::Tuple))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Kind::TupleStruct(ident(name)?)))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound, Error> {
+        let name = format!("{}::{}", ident(name)?, ident(variant)?);
+        Ok(Compound::new(Kind::TupleStruct(name)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound, Error> {
+        Ok(Compound::new(Kind::Map))
+    }
+
+    fn serialize_struct(self, name: &'static str, _len: usize) -> Result<Compound, Error> {
+        Ok(Compound::new(Kind::Struct(ident(name)?)))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound, Error> {
+        let name = format!("{}::{}", ident(name)?, ident(variant)?);
+        Ok(Compound::new(Kind::Struct(name)))
+    }
+}
+
+impl ser::SerializeSeq for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTuple for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeMap for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = Some(key.serialize(Expr)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let key = self
+            .key
+            .take()
+            .expect("serialize_value called before serialize_key");
+        let value = value.serialize(Expr)?;
+        self.items.push(format!("({}, {})", key, value));
+        Ok(())
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStruct for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), Error> {
+        Err(Error(format!("Skipped field `{}` isn't supported", key)))
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for Compound {
+    type Ok = String;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.field(key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), Error> {
+        Err(Error(format!("Skipped field `{}` isn't supported", key)))
+    }
+
+    fn end(self) -> Result<String, Error> {
+        Compound::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Settings {
+        name: String,
+        r#type: Role,
+        ports: Vec<u16>,
+        limits: BTreeMap<String, (f32, Option<u8>)>,
+        unit: Unit,
+    }
+
+    #[derive(Serialize)]
+    enum Role {
+        Server { threads: u8 },
+        Client(char, i32),
+    }
+
+    #[derive(Serialize)]
+    struct Unit;
+
+    #[test]
+    fn expressions() {
+        let mut limits = BTreeMap::new();
+        limits.insert("rate".to_string(), (1.5, None));
+        limits.insert("burst".to_string(), (f32::INFINITY, Some(3)));
+        let settings = Settings {
+            name: "a \"b\"".to_string(),
+            r#type: Role::Server { threads: 4 },
+            ports: vec![80, 443],
+            limits,
+            unit: Unit,
+        };
+
+        assert_eq!(
+            to_expr(&settings).unwrap(),
+            "Settings { name: ::std::convert::From::from(\"a \\\"b\\\"\"), \
+             r#type: Role::Server { threads: 4 }, \
+             ports: ::std::convert::From::from([80, 443]), \
+             limits: ::std::convert::From::from([\
+             (::std::convert::From::from(\"burst\"), (::std::f32::INFINITY, Some(3))), \
+             (::std::convert::From::from(\"rate\"), (1.5, None))]), \
+             unit: Unit }"
+        );
+        assert_eq!(
+            to_expr(&Role::Client('x', -1)).unwrap(),
+            "Role::Client('x', -1)"
+        );
+        assert_eq!(to_expr(&(1,)).unwrap(), "(1,)");
+    }
+
+    #[test]
+    fn renamed_fields() {
+        #[derive(Serialize)]
+        #[serde(rename_all = "kebab-case")]
+        struct Renamed {
+            max_size: u8,
+        }
+
+        assert!(to_expr(&Renamed { max_size: 1 }).is_err());
+    }
+}
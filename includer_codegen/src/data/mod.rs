@@ -0,0 +1,251 @@
+//! Validation of JSON, TOML and YAML files, and their deserialization into
+//! Rust values at build time.
+//!
+//! Everything is behind the `data` cargo feature.  [`Validate`] is a
+//! [`Transform`] that fails the build when a file has a syntax error, and
+//! [`Data`] is a pipeline that deserializes a file into a type of your own
+//! and generates it as a Rust expression, so that it's never parsed at
+//! runtime.
+//!
+//! ```ignore
+//! # use includer_codegen::prelude::*;
+//! use includer_codegen::data::Validate;
+//!
+//! Assets::new("FIXTURES", "../fixtures").transform(FilterRule::extension("yaml"), Validate);
+//! ```
+//!
+//! [`Validate`]: ./struct.Validate.html
+//! [`Transform`]: ../trait.Transform.html
+//! [`Data`]: ./struct.Data.html
+mod literal;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::str;
+use utils;
+use Entry;
+use Pipeline;
+use Transform;
+
+/// A data format that files can be validated and deserialized from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    /// Returns the format of the file from its extension, if it's known.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let ext = path.extension().and_then(OsStr::to_str)?.to_lowercase();
+        match ext.as_str() {
+            "json" => Some(Format::Json),
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Deserializes the data in this format.
+    pub fn parse<T: DeserializeOwned>(self, data: &[u8]) -> Result<T, String> {
+        match self {
+            Format::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Format::Toml => {
+                let data = str::from_utf8(data).map_err(|e| e.to_string())?;
+                toml::from_str(data).map_err(|e| e.to_string())
+            }
+            Format::Yaml => serde_norway::from_slice(data).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Validates JSON, TOML and YAML files by their extension, leaving them as
+/// is.
+///
+/// Files with another extension are an error.
+#[derive(Debug, Clone, Copy)]
+pub struct Validate;
+
+impl Transform for Validate {
+    fn transform(&self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        let format = Format::from_path(path).ok_or("Unknown data format")?;
+        format.parse::<IgnoredAny>(&data)?;
+        Ok(data)
+    }
+}
+
+/// Deserializes a file into a `T` at build time, and generates it as a
+/// `static` of type `LazyLock<T>`.
+///
+/// The value is written as a Rust expression of `T`, made of struct and enum
+/// literals, so `T` needs to be available to the build script, such as with a
+/// `#[path]` module.  The path of `T` is given as it's written where the code
+/// is included, and the types inside of it need to be in scope there.
+/// Strings, sequences and maps are created with `From::from`, so that
+/// `String`, `Cow<'static, str>`, `Vec` and the std maps and sets all work.
+///
+/// Types need to serialize the same way that they're declared.  Serde
+/// attributes that rename, skip or flatten fields, or that change how enums
+/// are tagged, aren't supported.
+///
+/// ```ignore
+/// # use includer_codegen::prelude::*;
+/// #[path = "src/settings.rs"]
+/// mod settings;
+///
+/// let settings = Data::<settings::Settings>::new(
+///     "SETTINGS",
+///     "settings::Settings",
+///     "settings.toml",
+/// );
+///
+/// Codegen::new().pipe(settings.build()).write();
+/// ```
+pub struct Data<T> {
+    ident: String,
+    type_path: String,
+    path: PathBuf,
+    format: Option<Format>,
+    ty: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned + Serialize> Data<T> {
+    /// Creates a new `Data` pipeline for the file at the path, with the path
+    /// of `T` where the code is included, such as `settings::Settings`.
+    pub fn new<I, S, P>(identifier: I, type_path: S, path: P) -> Self
+    where
+        I: Into<String>,
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        Data {
+            ident: identifier.into(),
+            type_path: type_path.into(),
+            path: path.into(),
+            format: None,
+            ty: PhantomData,
+        }
+    }
+
+    /// Sets the format of the file.
+    ///
+    /// Defaults to the format of its extension.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Boxes up the pipeline to pass to [`Codegen`] easily.
+    ///
+    /// [`Codegen`]: ../struct.Codegen.html
+    pub fn build(self) -> Box<Self> {
+        Box::new(self)
+    }
+}
+
+impl<T: DeserializeOwned + Serialize> fmt::Display for Data<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.generate(&[]))
+    }
+}
+
+impl<T: DeserializeOwned + Serialize> Pipeline for Data<T> {
+    /// # Panics
+    ///
+    /// Panics if the file can't be read or deserialized, or if `T` can't be
+    /// written as a Rust expression.
+    fn generate(&self, _entries: &[Entry]) -> String {
+        let path = &self.path;
+        utils::watch_path(path);
+
+        let format = self
+            .format
+            .or_else(|| Format::from_path(path))
+            .unwrap_or_else(|| panic!("Unknown data format of `{}`", path.display()));
+        let data = fs::read(path).unwrap_or_else(|_| panic!("Unable to read `{}`", path.display()));
+        let value: T = match format.parse(&data) {
+            Ok(value) => value,
+            Err(e) => panic!("Unable to parse `{}`: {}", path.display(), e),
+        };
+        let expr = match literal::to_expr(&value) {
+            Ok(expr) => expr,
+            Err(e) => panic!("Unable to generate `{}`: {}", path.display(), e),
+        };
+
+        format!(
+            "#[allow(clippy::useless_conversion)]\n\
+             static {}: ::std::sync::LazyLock<{}> = ::std::sync::LazyLock::new(|| {});\n",
+            self.ident, self.type_path, expr
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn formats() {
+        assert_eq!(Format::from_path(Path::new("a.YML")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("a.txt")), None);
+
+        let valid = |path: &str, data: &str| {
+            Validate
+                .transform(Path::new(path), data.as_bytes().to_vec())
+                .is_ok()
+        };
+        assert!(valid("a.json", r#"{"a": [1, 2]}"#));
+        assert!(!valid("a.json", r#"{"a": [1, 2}"#));
+        assert!(valid("a.toml", "a = 1\n[b]\nc = 'd'"));
+        assert!(!valid("a.toml", "a = "));
+        assert!(valid("a.yaml", "a:\n  - 1\n  - 2"));
+        assert!(!valid("a.yaml", "a: [1, 2"));
+        assert!(!valid("a.txt", ""));
+    }
+
+    #[test]
+    fn type_paths() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("ports.yaml");
+        fs::write(&path, "http: 80\nhttps: 443\n").unwrap();
+
+        let data = Data::<BTreeMap<String, u16>>::new("PORTS", "Ports", &path);
+        assert_eq!(
+            data.generate(&[]),
+            "#[allow(clippy::useless_conversion)]\n\
+             static PORTS: ::std::sync::LazyLock<Ports> = ::std::sync::LazyLock::new(|| \
+             ::std::convert::From::from([\
+             (::std::convert::From::from(\"http\"), 80), \
+             (::std::convert::From::from(\"https\"), 443)]));\n"
+        );
+    }
+
+    #[test]
+    fn keyword_fields() {
+        #[derive(Serialize, Deserialize)]
+        struct Listener {
+            r#type: String,
+            port: u16,
+        }
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("listener.toml");
+        fs::write(&path, "type = \"tcp\"\nport = 80\n").unwrap();
+
+        let data = Data::<Listener>::new("LISTENER", "net::Listener", &path);
+        assert_eq!(
+            data.generate(&[]),
+            "#[allow(clippy::useless_conversion)]\n\
+             static LISTENER: ::std::sync::LazyLock<net::Listener> = ::std::sync::LazyLock::new(|| \
+             Listener { r#type: ::std::convert::From::from(\"tcp\"), port: 80 });\n"
+        );
+    }
+}
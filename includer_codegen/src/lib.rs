@@ -1,19 +1,56 @@
 #[macro_use]
 extern crate quote;
+extern crate base64;
+#[cfg(any(feature = "web", feature = "compress"))]
+extern crate brotli;
+#[cfg(any(feature = "web", feature = "compress"))]
+extern crate flate2;
+#[cfg(feature = "images")]
+extern crate image;
+#[cfg(feature = "web")]
+extern crate includer;
+#[cfg(feature = "minify-css")]
+extern crate lightningcss;
+#[cfg(feature = "web")]
+extern crate mime_guess;
+#[cfg(feature = "minify-html")]
+extern crate minify_html;
+#[cfg(feature = "minify-js")]
+extern crate minify_js;
+#[cfg(feature = "images")]
+extern crate oxipng;
 extern crate proc_macro2;
 pub extern crate regex;
+#[cfg(feature = "data")]
+extern crate serde;
+#[cfg(any(feature = "minify-json", feature = "data"))]
+extern crate serde_json;
+#[cfg(feature = "data")]
+extern crate serde_norway;
+extern crate sha2;
+#[cfg(test)]
+extern crate tempfile;
+#[cfg(feature = "data")]
+extern crate toml;
 extern crate walkdir;
+#[cfg(any(feature = "web", feature = "compress"))]
+extern crate zstd;
 
 mod assets;
+#[cfg(feature = "data")]
+pub mod data;
+pub mod minify;
 pub mod prelude;
 pub mod utils;
 
 pub use assets::*;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use utils::{FilterRule, Pack};
 
 const DEFAULT_FILENAME: &str = "assets.rs";
 
@@ -26,11 +63,22 @@ const DEFAULT_FILENAME: &str = "assets.rs";
 /// An unlimited amount of [`Pipelines`] can be added and they will be written
 /// to the file in the same order as they were added.
 ///
+/// Every uri generated by the pipelines is checked for collisions, see
+/// [`on_collision`] for how they are handled.
+///
 /// [`Pipelines`]: ./trait.Pipeline.html
+/// [`on_collision`]: #method.on_collision
 #[derive(Default)]
 pub struct Codegen {
-    assets_builder: Vec<Box<Pipeline>>,
+    assets_builder: Vec<Box<dyn Pipeline>>,
     path: Option<PathBuf>,
+    collision_policy: CollisionPolicy,
+    pack: bool,
+    pack_alignments: Vec<(FilterRule, usize)>,
+    pack_file: Option<String>,
+    pack_fallback: bool,
+    pack_mmap: bool,
+    pack_verify: bool,
 }
 
 impl Codegen {
@@ -54,6 +102,13 @@ impl Codegen {
                 .ok()
                 .map(PathBuf::from)
                 .map(|dir| dir.join(DEFAULT_FILENAME)),
+            collision_policy: CollisionPolicy::Error,
+            pack: false,
+            pack_alignments: Vec::new(),
+            pack_file: None,
+            pack_fallback: false,
+            pack_mmap: false,
+            pack_verify: false,
         }
     }
 
@@ -67,7 +122,7 @@ impl Codegen {
     /// assert_eq!(c.path(), Some(Path::new("./out/gen.rs")));
     /// ```
     pub fn path(&self) -> Option<&Path> {
-        self.path.as_ref().map(PathBuf::as_path)
+        self.path.as_deref()
     }
 
     /// Sets the output path for the generated file.
@@ -83,7 +138,7 @@ impl Codegen {
     }
 
     /// Returns a list of all currently set Pipelines
-    pub fn pipelines(&self) -> &[Box<Pipeline>] {
+    pub fn pipelines(&self) -> &[Box<dyn Pipeline>] {
         &self.assets_builder
     }
 
@@ -96,11 +151,129 @@ impl Codegen {
     /// ```
     ///
     /// [`Pipeline`]: ./trait.Pipeline.html
-    pub fn pipe(mut self, generator: Box<Pipeline>) -> Codegen {
+    pub fn pipe(mut self, generator: Box<dyn Pipeline>) -> Codegen {
         self.assets_builder.push(generator);
         self
     }
 
+    /// Sets how duplicate uris across all pipelines are handled.
+    ///
+    /// Two entries collide when they normalize to the same uri, such as
+    /// `/docs/index.html` and a file named `/docs` in a [`WebAssets`]
+    /// pipeline, or two pipelines with overlapping prefixes.  Collisions are
+    /// checked across every pipeline in the order they were added.
+    ///
+    /// Defaults to [`CollisionPolicy::Error`].
+    ///
+    /// ```
+    /// use includer_codegen::prelude::*;
+    ///
+    /// Codegen::new().on_collision(CollisionPolicy::FirstWins);
+    /// ```
+    ///
+    /// [`WebAssets`]: ./struct.WebAssets.html
+    /// [`CollisionPolicy::Error`]: ./enum.CollisionPolicy.html#variant.Error
+    pub fn on_collision(mut self, policy: CollisionPolicy) -> Codegen {
+        self.collision_policy = policy;
+        self
+    }
+
+    /// Sets whether the data of every pipeline is packed into a single file.
+    ///
+    /// The pack is written into `OUT_DIR` and embedded with a single
+    /// `include_bytes!`, and assets are slices of it instead of embedding
+    /// every file on its own.  This keeps the generated code small and quick
+    /// to compile when there are many assets.  Pipelines that don't support
+    /// packing embed their data as usual, see [`Pipeline::generate_packed`].
+    ///
+    /// Defaults to `false`.
+    ///
+    /// ```
+    /// use includer_codegen::prelude::*;
+    ///
+    /// Codegen::new()
+    ///     .pack(true)
+    ///     .pack_alignment(FilterRule::extension("bin"), 16);
+    /// ```
+    ///
+    /// [`Pipeline::generate_packed`]: ./trait.Pipeline.html#method.generate_packed
+    pub fn pack(mut self, pack: bool) -> Codegen {
+        self.pack = pack;
+        self
+    }
+
+    /// Aligns the packed data of every file matching the rule.
+    ///
+    /// Rules are applied in the order that they were added, the first
+    /// matching rule determines the alignment.  Data is aligned to 1 byte by
+    /// default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the alignment isn't a power of two up to 2^29.
+    pub fn pack_alignment(mut self, rule: FilterRule, alignment: usize) -> Codegen {
+        utils::check_alignment(alignment);
+        self.pack_alignments.push((rule, alignment));
+        self
+    }
+
+    /// Writes the pack into a file instead of embedding it, and enables
+    /// packing.
+    ///
+    /// The file is written into `OUT_DIR`, and needs to be copied next to the
+    /// executable to ship it.  It's loaded with `includer::PackFile` on the
+    /// first access of an asset, which needs the `pack` cargo feature of
+    /// `includer`, from next to the executable or else from `OUT_DIR`.
+    /// Loading panics if the file is missing or not from the same build,
+    /// unless there is a [`pack_fallback`].
+    ///
+    /// The asset arrays are then `static` slices that are created on the
+    /// first access, instead of `const` arrays.
+    ///
+    /// ```
+    /// use includer_codegen::prelude::*;
+    ///
+    /// Codegen::new().pack_file("assets.pack").pack_fallback(true);
+    /// ```
+    ///
+    /// [`pack_fallback`]: #method.pack_fallback
+    pub fn pack_file<S: Into<String>>(mut self, name: S) -> Codegen {
+        self.pack = true;
+        self.pack_file = Some(name.into());
+        self
+    }
+
+    /// Sets whether the pack is also embedded, to fall back to if the pack
+    /// file can't be loaded.
+    ///
+    /// Defaults to `false`.
+    pub fn pack_fallback(mut self, fallback: bool) -> Codegen {
+        self.pack_fallback = fallback;
+        self
+    }
+
+    /// Sets whether the pack file is memory mapped instead of read into
+    /// memory when it's loaded.
+    ///
+    /// A mapped pack file must never be modified while the program runs.
+    /// Update it by writing the new pack into another file and renaming it
+    /// over the old one.  Mapped data is only aligned up to the page size.
+    /// Defaults to `false`.
+    pub fn pack_mmap(mut self, mmap: bool) -> Codegen {
+        self.pack_mmap = mmap;
+        self
+    }
+
+    /// Sets whether the data of the pack file is hashed when it's loaded,
+    /// instead of only checking the digest in its header.
+    ///
+    /// This catches a pack file that was corrupted after it was written, but
+    /// reads all of it up front, even when it's mapped.  Defaults to `false`.
+    pub fn pack_verify(mut self, verify: bool) -> Codegen {
+        self.pack_verify = verify;
+        self
+    }
+
     /// Writes everything to file and returns the written amount.
     ///
     /// ```no_run
@@ -116,19 +289,41 @@ impl Codegen {
     /// If `path` is not set then this function will panic.
     ///
     /// A panic will also happen when any file operation fails - such as
-    /// opening, writing, or closing.
+    /// opening, writing, or closing - or when two entries collide while the
+    /// collision policy is [`CollisionPolicy::Error`].
+    ///
+    /// [`CollisionPolicy::Error`]: ./enum.CollisionPolicy.html#variant.Error
     pub fn write(&self) -> usize {
         let path = self.path.as_ref().expect("Codegen output path not set");
+        let entries = self.assets_builder.iter().map(|p| p.entries()).collect();
+        let entries = resolve_collisions(entries, self.collision_policy);
+
         let file = File::create(path).expect("Unable to open a file at the path");
         let mut writer = BufWriter::new(file);
         let mut written = 0;
 
-        for assets in &self.assets_builder {
+        // without packing, the pack still shares identical data across pipelines
+        let mut pack = match self.pack_file {
+            Some(ref name) => Pack::new(&self.pack_alignments).external(
+                name,
+                self.pack_fallback,
+                self.pack_mmap,
+                self.pack_verify,
+            ),
+            None if self.pack => Pack::new(&self.pack_alignments),
+            None => Pack::unpacked(&self.pack_alignments),
+        };
+        for (assets, entries) in self.assets_builder.iter().zip(entries) {
+            let code = assets.generate_packed(&entries, &mut pack);
             written += writer
-                .write(assets.to_string().as_bytes())
+                .write(code.as_bytes())
                 .expect("Unable to write to Codegen file");
         }
 
+        written += writer
+            .write(pack.generate().as_bytes())
+            .expect("Unable to write to Codegen file");
+
         writer
             .flush()
             .expect("Unable to close written Codegen file");
@@ -145,4 +340,212 @@ impl Codegen {
 /// pipelines.
 ///
 /// [`Codegen`]: ./struct.Codegen.html
-pub trait Pipeline: Display {}
+pub trait Pipeline: Display {
+    /// Returns every entry that the pipeline would generate.
+    ///
+    /// [`Codegen`] uses these to detect uri collisions across all of its
+    /// pipelines.  The default implementation returns no entries, which opts
+    /// the pipeline out of collision detection.
+    ///
+    /// [`Codegen`]: ./struct.Codegen.html
+    fn entries(&self) -> Vec<Entry> {
+        Vec::new()
+    }
+
+    /// Generates the code for only the passed entries.
+    ///
+    /// The entries are a subset of [`entries`] with collisions removed.  The
+    /// default implementation ignores them and uses the `Display` output.
+    ///
+    /// [`entries`]: #method.entries
+    fn generate(&self, _entries: &[Entry]) -> String {
+        self.to_string()
+    }
+
+    /// Generates the code for only the passed entries, with their data in
+    /// the pack.
+    ///
+    /// Used instead of [`generate`] by [`Codegen`], which shares the data of
+    /// its pipelines in the pack whether or not it's packed.  The default
+    /// implementation doesn't use the pack.
+    ///
+    /// [`generate`]: #method.generate
+    /// [`Codegen`]: ./struct.Codegen.html
+    fn generate_packed(&self, entries: &[Entry], _pack: &mut Pack) -> String {
+        self.generate(entries)
+    }
+}
+
+/// A build-time transformation of the files that a [`Pipeline`] embeds.
+///
+/// Pipelines apply transforms to the files matching a [`FilterRule`], and
+/// embed the output written to `OUT_DIR` instead of the original file.  The
+/// original file is still watched for changes.
+///
+/// Transforms are implemented for closures with the same signature as
+/// [`transform`].
+///
+/// ```
+/// use includer_codegen::prelude::*;
+/// use std::path::Path;
+///
+/// let build = |_: &Path, data: Vec<u8>| -> Result<Vec<u8>, String> {
+///     let config = String::from_utf8(data).map_err(|e| e.to_string())?;
+///     Ok(config.replace("{{VERSION}}", "1.0.0").into_bytes())
+/// };
+///
+/// Assets::new("ASSETS", "../web/dist").transform(FilterRule::regex(r"config\.js$"), build);
+/// ```
+///
+/// [`Pipeline`]: ./trait.Pipeline.html
+/// [`FilterRule`]: ./utils/enum.FilterRule.html
+/// [`transform`]: #tymethod.transform
+pub trait Transform {
+    /// Transforms the contents of the file at `path`.
+    ///
+    /// An error fails the build with the path of the file.
+    fn transform(&self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String>;
+}
+
+impl<F> Transform for F
+where
+    F: Fn(&Path, Vec<u8>) -> Result<Vec<u8>, String>,
+{
+    fn transform(&self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        self(path, data)
+    }
+}
+
+/// A single asset that a [`Pipeline`] generates.
+///
+/// [`Pipeline`]: ./trait.Pipeline.html
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The normalized uri that the asset is available at.
+    pub uri: String,
+
+    /// The path to the file that the asset is read from.
+    pub path: PathBuf,
+
+    /// The uri before it was fingerprinted, if it was.
+    pub logical_uri: Option<String>,
+}
+
+impl Entry {
+    /// The uri that the asset is referred to by, before any fingerprint.
+    pub fn logical_uri(&self) -> &str {
+        self.logical_uri.as_ref().unwrap_or(&self.uri)
+    }
+}
+
+/// How [`Codegen`] handles multiple entries that normalize to the same uri.
+///
+/// [`Codegen`]: ./struct.Codegen.html
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CollisionPolicy {
+    /// Panic with both source paths of the colliding entries.
+    #[default]
+    Error,
+
+    /// Keep the entry that was found first and warn about the rest.
+    FirstWins,
+
+    /// Keep the entry that was found last and warn about the rest.
+    LastWins,
+}
+
+/// Removes all colliding entries according to the policy, keeping the order
+/// of the remaining entries intact.
+fn resolve_collisions(entries: Vec<Vec<Entry>>, policy: CollisionPolicy) -> Vec<Vec<Entry>> {
+    let mut seen: HashMap<String, (usize, usize)> = HashMap::new();
+    let mut removed = HashSet::new();
+
+    for (p, pipeline) in entries.iter().enumerate() {
+        for (e, entry) in pipeline.iter().enumerate() {
+            let (first_p, first_e) = match seen.get(&entry.uri) {
+                Some(&index) => index,
+                None => {
+                    seen.insert(entry.uri.clone(), (p, e));
+                    continue;
+                }
+            };
+
+            let first = &entries[first_p][first_e];
+            let report = format!(
+                "uri `{}` is generated by both `{}` and `{}`",
+                entry.uri,
+                first.path.display(),
+                entry.path.display()
+            );
+
+            match policy {
+                CollisionPolicy::Error => panic!("Duplicate {}", report),
+                CollisionPolicy::FirstWins => {
+                    println!("cargo:warning=duplicate {}, keeping the first", report);
+                    removed.insert((p, e));
+                }
+                CollisionPolicy::LastWins => {
+                    println!("cargo:warning=duplicate {}, keeping the last", report);
+                    removed.insert((first_p, first_e));
+                    seen.insert(entry.uri.clone(), (p, e));
+                }
+            }
+        }
+    }
+
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(p, pipeline)| {
+            pipeline
+                .into_iter()
+                .enumerate()
+                .filter(|(e, _)| !removed.contains(&(p, *e)))
+                .map(|(_, entry)| entry)
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uri: &str, path: &str) -> Entry {
+        Entry {
+            uri: uri.to_string(),
+            path: PathBuf::from(path),
+            logical_uri: None,
+        }
+    }
+
+    fn colliding() -> Vec<Vec<Entry>> {
+        vec![
+            vec![
+                entry("/docs", "dist/docs/index.html"),
+                entry("/a.js", "dist/a.js"),
+            ],
+            vec![entry("/docs", "extra/docs")],
+        ]
+    }
+
+    #[test]
+    fn first_wins() {
+        let resolved = resolve_collisions(colliding(), CollisionPolicy::FirstWins);
+        assert_eq!(resolved[0].len(), 2);
+        assert!(resolved[1].is_empty());
+    }
+
+    #[test]
+    fn last_wins() {
+        let resolved = resolve_collisions(colliding(), CollisionPolicy::LastWins);
+        assert_eq!(resolved[0], vec![entry("/a.js", "dist/a.js")]);
+        assert_eq!(resolved[1], vec![entry("/docs", "extra/docs")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "dist/docs/index.html")]
+    fn error() {
+        resolve_collisions(colliding(), CollisionPolicy::Error);
+    }
+}
@@ -0,0 +1,205 @@
+//! Built-in minifying [`Transforms`].
+//!
+//! Every minifier is behind the cargo feature of its format: `minify-html`,
+//! `minify-css`, `minify-js` and `minify-json`.  The minified output is cached
+//! in `OUT_DIR`, keyed by the hash of the input, so unchanged files aren't
+//! minified again by incremental builds.
+//!
+//! ```ignore
+//! # use includer_codegen::prelude::*;
+//! use includer_codegen::minify;
+//!
+//! Assets::new("ASSETS", "../resources").transform(FilterRule::extension("json"), minify::Json);
+//! ```
+//!
+//! [`WebAssets::minify`] applies every enabled minifier by file extension.
+//!
+//! [`Transforms`]: ../trait.Transform.html
+//! [`WebAssets::minify`]: ../struct.WebAssets.html#method.minify
+use std::ffi::OsStr;
+use std::path::Path;
+#[cfg(any(
+    feature = "minify-html",
+    feature = "minify-css",
+    feature = "minify-js",
+    feature = "minify-json"
+))]
+use utils::cached;
+use Transform;
+
+/// Minifies HTML, including inline CSS and javascript.
+#[cfg(feature = "minify-html")]
+#[derive(Debug, Clone, Copy)]
+pub struct Html;
+
+#[cfg(feature = "minify-html")]
+impl Transform for Html {
+    fn transform(&self, _path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        cached("minify-html", data, |data| {
+            let mut cfg = minify_html::Cfg::spec_compliant();
+            cfg.minify_css = true;
+            cfg.minify_js = true;
+            Ok(minify_html::minify(&data, &cfg))
+        })
+    }
+}
+
+/// Minifies CSS.
+#[cfg(feature = "minify-css")]
+#[derive(Debug, Clone, Copy)]
+pub struct Css;
+
+#[cfg(feature = "minify-css")]
+impl Transform for Css {
+    fn transform(&self, _path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        use lightningcss::stylesheet::{MinifyOptions, ParserOptions, PrinterOptions, StyleSheet};
+
+        cached("minify-css", data, |data| {
+            let source = String::from_utf8(data).map_err(|e| e.to_string())?;
+            let mut sheet =
+                StyleSheet::parse(&source, ParserOptions::default()).map_err(|e| e.to_string())?;
+            sheet
+                .minify(MinifyOptions::default())
+                .map_err(|e| e.to_string())?;
+            let printer = PrinterOptions {
+                minify: true,
+                ..PrinterOptions::default()
+            };
+            let css = sheet.to_css(printer).map_err(|e| e.to_string())?;
+            Ok(css.code.into_bytes())
+        })
+    }
+}
+
+/// Minifies javascript.
+///
+/// Classic scripts keep the names of their top level declarations, so that
+/// other scripts can still use them.
+#[cfg(feature = "minify-js")]
+#[derive(Debug, Clone, Copy)]
+pub enum Js {
+    /// A classic script.
+    Script,
+    /// An ECMAScript module.
+    Module,
+}
+
+#[cfg(feature = "minify-js")]
+impl Transform for Js {
+    fn transform(&self, _path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        use minify_js::{Session, TopLevelMode};
+
+        let (name, mode) = match self {
+            Js::Script => ("minify-js", TopLevelMode::Global),
+            Js::Module => ("minify-mjs", TopLevelMode::Module),
+        };
+        cached(name, data, |data| {
+            let session = Session::new();
+            let mut out = Vec::new();
+            minify_js::minify(&session, mode, &data, &mut out).map_err(|e| e.to_string())?;
+            Ok(out)
+        })
+    }
+}
+
+/// Validates and minifies JSON.
+///
+/// Only the whitespace between tokens is removed, the order of object keys
+/// and the formatting of numbers are kept.
+#[cfg(feature = "minify-json")]
+#[derive(Debug, Clone, Copy)]
+pub struct Json;
+
+#[cfg(feature = "minify-json")]
+impl Transform for Json {
+    fn transform(&self, _path: &Path, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        cached("minify-json", data, |data| {
+            serde_json::from_slice::<serde_json::Value>(&data).map_err(|e| e.to_string())?;
+
+            let mut out = Vec::with_capacity(data.len());
+            let (mut string, mut escaped) = (false, false);
+            for byte in data {
+                if string {
+                    if escaped {
+                        escaped = false;
+                    } else if byte == b'\\' {
+                        escaped = true;
+                    } else if byte == b'"' {
+                        string = false;
+                    }
+                } else if byte == b'"' {
+                    string = true;
+                } else if b" \t\r\n".contains(&byte) {
+                    continue;
+                }
+                out.push(byte);
+            }
+
+            Ok(out)
+        })
+    }
+}
+
+/// Returns the enabled minifier for the extension of the file, if any.
+#[cfg_attr(not(feature = "web"), allow(dead_code))]
+pub(crate) fn for_path(path: &Path) -> Option<Box<dyn Transform>> {
+    let ext = path.extension().and_then(OsStr::to_str)?.to_lowercase();
+    match ext.as_str() {
+        #[cfg(feature = "minify-html")]
+        "html" | "htm" => Some(Box::new(Html)),
+        #[cfg(feature = "minify-css")]
+        "css" => Some(Box::new(Css)),
+        #[cfg(feature = "minify-js")]
+        "js" => Some(Box::new(Js::Script)),
+        #[cfg(feature = "minify-js")]
+        "mjs" => Some(Box::new(Js::Module)),
+        #[cfg(feature = "minify-json")]
+        "json" => Some(Box::new(Json)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "minify-json")]
+    #[test]
+    fn json_keeps_strings() {
+        let json = b"{\n  \"b\": \"a \\\" b\",\n  \"a\": [1.50, 2]\n}\n".to_vec();
+        assert_eq!(
+            Json.transform(Path::new("data.json"), json).unwrap(),
+            b"{\"b\":\"a \\\" b\",\"a\":[1.50,2]}".to_vec()
+        );
+        assert!(Json
+            .transform(Path::new("data.json"), b"{,}".to_vec())
+            .is_err());
+    }
+
+    #[cfg(feature = "minify-css")]
+    #[test]
+    fn css() {
+        let css = b"body {\n  color: #ff0000;\n}\n".to_vec();
+        assert_eq!(
+            Css.transform(Path::new("style.css"), css).unwrap(),
+            b"body{color:red}".to_vec()
+        );
+    }
+
+    #[cfg(feature = "minify-js")]
+    #[test]
+    fn js_keeps_globals() {
+        let js = b"function greet(name) {\n  return 'Hi ' + name;\n}\n".to_vec();
+        let minified = Js::Script.transform(Path::new("app.js"), js).unwrap();
+        assert!(String::from_utf8(minified).unwrap().contains("greet"));
+    }
+
+    #[test]
+    fn minifiers_by_extension() {
+        assert!(for_path(Path::new("README")).is_none());
+        assert_eq!(
+            for_path(Path::new("data.json")).is_some(),
+            cfg!(feature = "minify-json")
+        );
+    }
+}
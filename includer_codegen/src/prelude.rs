@@ -7,8 +7,16 @@
 //! [`Pipeline`]: ../trait.Pipeline.html
 //! [`utils`]: ../utils/index.html
 
+#[cfg(feature = "data")]
+pub use data::Data;
 pub use utils::Filter;
+pub use utils::FilterRule;
+pub use utils::Mount;
 pub use Assets;
+#[cfg(feature = "compress")]
+pub use Codec;
 pub use Codegen;
+pub use CollisionPolicy;
+pub use Transform;
 #[cfg(feature = "web")]
-pub use WebAssets;
+pub use {SidecarPolicy, SriAlgorithm, TrailingSlash, Variant, WebAssets};
@@ -5,8 +5,19 @@
 //!
 //! [`Pipelines`]: ../trait.Pipeline.html
 use self::Filter::*;
+use self::FilterListType::*;
+use proc_macro2::{Ident, Literal, Span, TokenStream};
 use regex::Regex;
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+use CollisionPolicy;
+use Entry;
+use Transform;
 
 /// The type of filter list to use.
 pub enum FilterListType {
@@ -175,6 +186,859 @@ impl FilterRule {
     }
 }
 
+/// A directory of assets that is mounted at a uri prefix.
+///
+/// Pipelines that accept multiple mounts merge all of their entries into a
+/// single asset set.  This is useful when a single uri space is served from
+/// multiple directories, such as a web root and a shared branding directory.
+///
+/// ```
+/// # use includer_codegen::prelude::*;
+/// #
+/// Mount::new("../branding")
+///     .prefix("/branding")
+///     .whitelist()
+///     .filter(Filter::include_extension("svg"));
+/// ```
+pub struct Mount {
+    path: PathBuf,
+    prefix: String,
+    filters: Vec<Filter>,
+    filter_list_type: FilterListType,
+}
+
+impl Mount {
+    /// Creates a new `Mount` at the root uri `"/"`.
+    ///
+    /// By default, the filter list type is a blacklist.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Mount {
+            path: path.into(),
+            prefix: "/".to_string(),
+            filters: Vec::new(),
+            filter_list_type: Blacklist,
+        }
+    }
+
+    /// Add a filter to the mount.
+    ///
+    /// Filters are applied in the order that they were added, the first
+    /// matching filter determines how the file entry is handled.
+    ///
+    /// If there are no filters then all files are matched.  If there are no
+    /// filters and it's a whitelist, no files are matched.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Sets the prefix to use for the normalized path uri.
+    ///
+    /// Defaults to `"/"`
+    pub fn prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the path to the mounted directory.
+    pub fn set_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Set the filter list type to a blacklist.
+    pub fn blacklist(mut self) -> Self {
+        self.filter_list_type = Blacklist;
+        self
+    }
+
+    /// Set the filter list type to a whitelist.
+    pub fn whitelist(mut self) -> Self {
+        self.filter_list_type = Whitelist;
+        self
+    }
+
+    /// Returns the path to the mounted directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Walks the mounted directory and returns an entry for every matched file.
+    ///
+    /// The uri of each entry is the path relative to the mounted directory
+    /// joined onto the prefix.  Every directory and matched file is watched
+    /// with [`watch_path`].
+    ///
+    /// [`watch_path`]: ./fn.watch_path.html
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let walker = WalkDir::new(&self.path).sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        for maybe_entry in walker {
+            let entry = maybe_entry.expect("Couldn't read DirEntry");
+
+            // We don't have special rules for directories, but we can't use
+            // walkdir's entry filter because we don't want files under
+            // directories to be skipped.
+            if entry.file_type().is_dir() {
+                watch_path(entry.path());
+                continue;
+            }
+
+            if self.matches(entry.path()) {
+                watch_path(entry.path());
+                entries.push(Entry {
+                    uri: self.uri(entry.path()),
+                    path: entry.path().to_path_buf(),
+                    logical_uri: None,
+                });
+            }
+        }
+
+        entries
+    }
+
+    /// Whether the file is matched by the filters.
+    ///
+    /// The first matching filter determines if the file is matched, and files
+    /// that no filter matches are included.
+    fn matches(&self, path: &Path) -> bool {
+        if self.filters.is_empty() {
+            return matches!(self.filter_list_type, Blacklist);
+        }
+
+        let filter = self.filters.iter().find(|filter| filter.matches(path));
+        !matches!(filter, Some(Exclude(_)))
+    }
+
+    fn uri(&self, path: &Path) -> String {
+        let relative = path
+            .strip_prefix(&self.path)
+            .expect("Couldn't strip path prefix");
+        let path = PathBuf::from("/").join(&self.prefix).join(relative);
+        path_to_string(path)
+    }
+}
+
+/// Merges the entries of multiple mounts into a single list.
+///
+/// When an entry has the same uri as an entry from an earlier mount, the
+/// policy decides which one is kept.  The kept entry takes the position of
+/// the first one.  Duplicates inside of a single mount are left alone so that
+/// [`Codegen`] can report them.
+///
+/// [`Codegen`]: ../struct.Codegen.html
+pub fn merge_mounts(mounts: Vec<Vec<Entry>>, policy: CollisionPolicy) -> Vec<Entry> {
+    let mut merged: Vec<Entry> = Vec::new();
+    let mut indices: HashMap<String, usize> = HashMap::new();
+
+    for entries in mounts {
+        let previous = merged.len();
+        for entry in entries {
+            match (indices.get(&entry.uri).copied(), policy) {
+                (None, _) => merged.push(entry),
+                (Some(_), CollisionPolicy::FirstWins) => {}
+                (Some(i), CollisionPolicy::LastWins) => merged[i] = entry,
+                (Some(i), CollisionPolicy::Error) => panic!(
+                    "Duplicate uri `{}` is mounted from both `{}` and `{}`",
+                    entry.uri,
+                    merged[i].path.display(),
+                    entry.path.display()
+                ),
+            }
+        }
+
+        // only entries of earlier mounts are replaced
+        for (i, entry) in merged.iter().enumerate().skip(previous) {
+            indices.entry(entry.uri.clone()).or_insert(i);
+        }
+    }
+
+    merged
+}
+
+/// Watches every single file entry and returns them.
+///
+/// # Panics
+///
+/// Panics if any of the files do not exist.
+pub fn watch_files(files: &[Entry]) -> Vec<Entry> {
+    for file in files {
+        if !file.path.is_file() {
+            panic!("Added file `{}` does not exist", file.path.display());
+        }
+        watch_path(&file.path);
+    }
+
+    files.to_vec()
+}
+
+/// Appends an entry for every `(from_uri, to_uri)` alias.
+///
+/// The alias entry has the same path as the entry at `to_uri`, so that
+/// pipelines can share the embedded data between both of them.  Entries are
+/// found by their uri before fingerprinting.
+///
+/// # Panics
+///
+/// Panics if there is no entry at `to_uri`.
+pub fn add_aliases(entries: &mut Vec<Entry>, aliases: &[(String, String)]) {
+    for (from, to) in aliases {
+        let path = match entries.iter().find(|e| e.logical_uri() == to) {
+            Some(target) => target.path.clone(),
+            None => panic!("Alias `{}` points to `{}` which isn't an asset", from, to),
+        };
+
+        entries.push(Entry {
+            uri: from.clone(),
+            path,
+            logical_uri: None,
+        });
+    }
+}
+
+/// Returns the unique paths of the entries, and for every entry the index of
+/// its path in the unique paths.
+pub(crate) fn unique_paths(entries: &[Entry]) -> (Vec<&Path>, Vec<usize>) {
+    let mut seen = HashMap::new();
+    let mut paths = Vec::new();
+
+    let indices = entries
+        .iter()
+        .map(|entry| {
+            *seen.entry(entry.path.as_path()).or_insert_with(|| {
+                paths.push(entry.path.as_path());
+                paths.len() - 1
+            })
+        })
+        .collect();
+
+    (paths, indices)
+}
+
+/// Returns the paths with unique contents along with the length of their
+/// contents, and for every path the index of its contents in the unique
+/// paths.
+///
+/// Files are compared by the hash of their contents, so that identical files
+/// are only embedded once no matter where they are.
+pub(crate) fn unique_contents<'a, I>(files: I) -> (Vec<(&'a Path, usize)>, Vec<usize>)
+where
+    I: IntoIterator<Item = (&'a Path, Vec<u8>)>,
+{
+    let mut seen = HashMap::new();
+    let mut unique = Vec::new();
+
+    let indices = files
+        .into_iter()
+        .map(|(path, data)| {
+            *seen
+                .entry(Sha256::digest(&data).to_vec())
+                .or_insert_with(|| {
+                    unique.push((path, data.len()));
+                    unique.len() - 1
+                })
+        })
+        .collect();
+
+    (unique, indices)
+}
+
+/// A file to embed, see [`embed`].
+///
+/// [`embed`]: ./fn.embed.html
+pub(crate) struct EmbedFile<'a> {
+    /// The file that the data is for.
+    pub path: &'a Path,
+
+    /// The path of the data to embed.
+    pub data: &'a Path,
+
+    pub alignment: usize,
+
+    /// Whether the data is UTF-8 text, which is validated.
+    pub text: bool,
+}
+
+impl<'a> EmbedFile<'a> {
+    pub fn new(path: &'a Path, data: &'a Path) -> Self {
+        EmbedFile {
+            path,
+            data,
+            alignment: 1,
+            text: false,
+        }
+    }
+
+    /// Reads the data, validating it if it's text.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data can't be read or text isn't valid UTF-8.
+    fn read(&self) -> Vec<u8> {
+        let data = fs::read(self.data)
+            .unwrap_or_else(|_| panic!("Unable to read `{}`", self.data.display()));
+        if self.text {
+            if let Err(e) = std::str::from_utf8(&data) {
+                panic!("`{}` is not valid UTF-8: {}", self.path.display(), e);
+            }
+        }
+        data
+    }
+}
+
+/// The embedded data of the files of a pipeline.
+pub(crate) struct Embedded {
+    /// The items defining the data, to generate before it's used.
+    pub items: Vec<TokenStream>,
+
+    /// An expression of type `&'static [u8]` for every distinct data.
+    pub refs: Vec<TokenStream>,
+
+    /// An expression of type `&'static str` for every distinct data that is
+    /// text.
+    pub texts: Vec<Option<TokenStream>>,
+
+    /// The index into `refs` of the data of every file.
+    pub indices: Vec<usize>,
+
+    /// Whether the data is in a pack file, so that it's only available at
+    /// runtime and the items are `let` statements.
+    pub external: bool,
+}
+
+/// Embeds the data of every file.
+///
+/// Identical data is only embedded once, at the largest alignment it's
+/// embedded with.  Without a pack, every distinct data is a `static` byte
+/// array named `DATA_{index}`, wrapped in an `Align{alignment}` struct if it's
+/// aligned, or a `static` string from `include_str!` if it's unaligned text.
+/// With a pack, it's the data from the pack named the same, which is shared
+/// with the other pipelines.
+///
+/// Every file is read once.
+///
+/// # Panics
+///
+/// Panics if a file can't be read or text isn't valid UTF-8.
+pub(crate) fn embed(files: &[EmbedFile], pack: Option<&mut Pack>) -> Embedded {
+    let data_ident = |k: usize| Ident::new(&format!("DATA_{}", k), Span::call_site());
+    let mut items = Vec::new();
+    let mut refs = Vec::new();
+    let external = pack.as_ref().is_some_and(|pack| pack.is_external());
+
+    let (indices, mut texts) = match pack {
+        Some(pack) => {
+            let mut seen = HashMap::new();
+            let indices: Vec<_> = files
+                .iter()
+                .map(|file| {
+                    let range = pack.insert(file.path, file.data, file.read(), file.alignment);
+                    *seen.entry(range).or_insert_with(|| {
+                        let ident = data_ident(refs.len());
+                        let slice: TokenStream = pack.slice(range.0, range.1).parse().unwrap();
+                        items.push(if external {
+                            quote! {
+                                #[allow(non_snake_case)]
+                                let #ident: &'static [u8] = #slice;
+                            }
+                        } else {
+                            quote! {
+                                const #ident: &[u8] = #slice;
+                            }
+                        });
+                        refs.push(quote! {#ident});
+                        refs.len() - 1
+                    })
+                })
+                .collect();
+            (indices, vec![None; refs.len()])
+        }
+        None => {
+            let data = files.iter().map(|file| (file.data, file.read()));
+            let (unique, indices) = unique_contents(data);
+            let mut alignments = vec![1; unique.len()];
+            let mut is_text = vec![false; unique.len()];
+            for (file, &k) in files.iter().zip(&indices) {
+                alignments[k] = alignments[k].max(file.alignment);
+                is_text[k] |= file.text;
+            }
+
+            let mut texts = Vec::new();
+            let mut wrappers = HashSet::new();
+            for (k, (&(path, len), &alignment)) in unique.iter().zip(&alignments).enumerate() {
+                let ident = data_ident(k);
+                let path = path_to_string(path);
+                if alignment == 1 && is_text[k] {
+                    items.push(quote! {
+                        static #ident: &str = include_str!(#path);
+                    });
+                    refs.push(quote! {#ident.as_bytes()});
+                    texts.push(Some(quote! {#ident}));
+                    continue;
+                }
+
+                texts.push(None);
+                if alignment == 1 {
+                    items.push(quote! {
+                        static #ident: [u8; #len] = *include_bytes!(#path);
+                    });
+                    refs.push(quote! {&#ident});
+                    continue;
+                }
+
+                let wrapper = Ident::new(&format!("Align{}", alignment), Span::call_site());
+                if wrappers.insert(alignment) {
+                    let alignment = Literal::usize_unsuffixed(alignment);
+                    items.push(quote! {
+                        #[repr(C, align(#alignment))]
+                        struct #wrapper<T>(T);
+                    });
+                }
+                items.push(quote! {
+                    static #ident: #wrapper<[u8; #len]> = #wrapper(*include_bytes!(#path));
+                });
+                refs.push(quote! {&#ident.0});
+            }
+            (indices, texts)
+        }
+    };
+
+    // text that isn't from `include_str!` was validated above, and is checked
+    // again by `from_utf8` to borrow it as a string without unsafe code
+    for (file, &k) in files.iter().zip(&indices) {
+        if file.text && texts[k].is_none() {
+            let data = &refs[k];
+            texts[k] = Some(quote! {
+                match ::std::str::from_utf8(#data) {
+                    Ok(text) => text,
+                    Err(_) => panic!("Embedded text is not valid UTF-8"),
+                }
+            });
+        }
+    }
+
+    Embedded {
+        items,
+        refs,
+        texts,
+        indices,
+        external,
+    }
+}
+
+/// Generates the array of the assets of a pipeline, of type `ty`, from the
+/// items defining their data and their structs.
+///
+/// The array is a `const`, unless the data is in a pack file, in which case
+/// it's a `static` slice that is created on the first access.  The slice
+/// dereferences to `&[T]`, so it's used like the array.
+pub(crate) fn asset_array(
+    ident: &str,
+    ty: &str,
+    items: &[TokenStream],
+    structs: &[TokenStream],
+    external: bool,
+) -> TokenStream {
+    let ident = Ident::new(ident, Span::call_site());
+    let ty = Ident::new(ty, Span::call_site());
+    if external {
+        quote! {
+            static #ident: ::std::sync::LazyLock<&'static [::includer::#ty]> = ::std::sync::LazyLock::new(|| {
+                #(#items)*
+                ::std::boxed::Box::leak(::std::boxed::Box::new([#(#structs),*]))
+            });
+        }
+    } else {
+        let len = structs.len();
+        quote! {
+            const #ident: [::includer::#ty; #len] = {
+                #(#items)*
+                [#(#structs),*]
+            };
+        }
+    }
+}
+
+/// Returns the alignment of the first rule matching the path, or 1 if none
+/// match.
+pub(crate) fn alignment(rules: &[(FilterRule, usize)], path: &Path) -> usize {
+    rules
+        .iter()
+        .find(|(rule, _)| rule.matches(path))
+        .map_or(1, |&(_, alignment)| alignment)
+}
+
+/// Panics if the alignment can't be used for embedded data.
+pub(crate) fn check_alignment(alignment: usize) {
+    if !alignment.is_power_of_two() || alignment > 1 << 29 {
+        panic!("Alignment {} is not a power of two up to 2^29", alignment);
+    }
+}
+
+/// A single file that the data of every pipeline is concatenated into.
+///
+/// [`Codegen`] creates the pack and passes it to [`Pipeline::generate_packed`],
+/// so that identical data is shared across all of its pipelines.  The pack
+/// is embedded with a single `include_bytes!`, as a `static` named
+/// `INCLUDER_PACK` that is aligned to the largest alignment of its data.  An
+/// external pack is instead written into a pack file in `OUT_DIR`, and
+/// `INCLUDER_PACK` is an `includer::PackFile` that loads it.
+///
+/// When packing isn't enabled, the data isn't concatenated and every distinct
+/// data is instead a `static` of its own, named `INCLUDER_DATA_{index}`.
+///
+/// [`Codegen`]: ../struct.Codegen.html
+/// [`Pipeline::generate_packed`]: ../trait.Pipeline.html#method.generate_packed
+pub struct Pack<'a> {
+    alignments: &'a [(FilterRule, usize)],
+    data: Vec<u8>,
+    offsets: HashMap<Vec<u8>, Vec<(usize, usize)>>,
+    alignment: usize,
+    file: Option<PackFileOptions<'a>>,
+
+    /// The path, length and alignment of every distinct data, if the data
+    /// is embedded as separate statics instead of being packed.
+    statics: Option<Vec<(PathBuf, usize, usize)>>,
+}
+
+/// How an external pack file is loaded, see `includer::PackFile`.
+struct PackFileOptions<'a> {
+    name: &'a str,
+    fallback: bool,
+    mapped: bool,
+    verified: bool,
+}
+
+/// The version of the pack file format, see `includer::pack`.
+const PACK_VERSION: u32 = 1;
+
+impl<'a> Pack<'a> {
+    pub(crate) fn new(alignments: &'a [(FilterRule, usize)]) -> Self {
+        Pack {
+            alignments,
+            data: Vec::new(),
+            offsets: HashMap::new(),
+            alignment: 1,
+            file: None,
+            statics: None,
+        }
+    }
+
+    /// Creates a pack that doesn't concatenate its data, but embeds every
+    /// distinct data as a separate `static`.
+    pub(crate) fn unpacked(alignments: &'a [(FilterRule, usize)]) -> Self {
+        Pack {
+            statics: Some(Vec::new()),
+            ..Pack::new(alignments)
+        }
+    }
+
+    /// Makes the pack an external pack file with the name, that is embedded
+    /// as a fallback if `fallback` is set, memory mapped if `mapped` is, and
+    /// hashed when it's loaded if `verified` is.
+    pub(crate) fn external(
+        mut self,
+        name: &'a str,
+        fallback: bool,
+        mapped: bool,
+        verified: bool,
+    ) -> Self {
+        self.file = Some(PackFileOptions {
+            name,
+            fallback,
+            mapped,
+            verified,
+        });
+        self
+    }
+
+    /// Whether the pack is a file that is loaded at runtime.
+    ///
+    /// The data of an external pack isn't available in constants, see
+    /// [`slice`].
+    ///
+    /// [`slice`]: #method.slice
+    pub fn is_external(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Adds the data at `data_path` to the pack, returning its offset and
+    /// length.  Without packing, the offset is the index of the data.
+    ///
+    /// `path` is the file that the data is for, which the alignment rules are
+    /// matched against.  Data that is already in the pack at a suitable
+    /// alignment isn't added again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the data can't be read.
+    pub fn add(&mut self, path: &Path, data_path: &Path) -> (usize, usize) {
+        self.add_aligned(path, data_path, 1)
+    }
+
+    /// Adds the data at `data_path` to the pack like [`add`], aligned to at
+    /// least `alignment`.
+    ///
+    /// [`add`]: #method.add
+    pub fn add_aligned(
+        &mut self,
+        path: &Path,
+        data_path: &Path,
+        alignment: usize,
+    ) -> (usize, usize) {
+        let data = fs::read(data_path)
+            .unwrap_or_else(|_| panic!("Unable to read `{}`", data_path.display()));
+        self.insert(path, data_path, data, alignment)
+    }
+
+    /// Adds the data that was read from `data_path` like [`add_aligned`].
+    ///
+    /// [`add_aligned`]: #method.add_aligned
+    pub(crate) fn insert(
+        &mut self,
+        path: &Path,
+        data_path: &Path,
+        data: Vec<u8>,
+        alignment: usize,
+    ) -> (usize, usize) {
+        let alignment = alignment.max(self::alignment(self.alignments, path));
+
+        let offsets = self
+            .offsets
+            .entry(Sha256::digest(&data).to_vec())
+            .or_default();
+        if let Some(ref mut statics) = self.statics {
+            let aligned = |&&(k, _): &&(usize, usize)| statics[k].2 % alignment == 0;
+            if let Some(&range) = offsets.iter().find(aligned) {
+                return range;
+            }
+
+            statics.push((data_path.to_path_buf(), data.len(), alignment));
+            let range = (statics.len() - 1, data.len());
+            offsets.push(range);
+            return range;
+        }
+
+        if let Some(&range) = offsets.iter().find(|(offset, _)| offset % alignment == 0) {
+            return range;
+        }
+
+        let padding = (alignment - self.data.len() % alignment) % alignment;
+        self.data.resize(self.data.len() + padding, 0);
+        let range = (self.data.len(), data.len());
+        self.data.extend(data);
+        self.alignment = self.alignment.max(alignment);
+        offsets.push(range);
+        range
+    }
+
+    /// Returns an expression of type `&'static [u8]` for the data at the
+    /// offset, usable in constants unless the pack is external.
+    pub fn slice(&self, offset: usize, len: usize) -> String {
+        if let Some(ref statics) = self.statics {
+            let (_, _, alignment) = statics[offset];
+            return if alignment == 1 {
+                format!("&INCLUDER_DATA_{}", offset)
+            } else {
+                format!("&INCLUDER_DATA_{}.0", offset)
+            };
+        }
+
+        if self.is_external() {
+            format!("INCLUDER_PACK.slice({}, {})", offset, len)
+        } else {
+            format!("INCLUDER_PACK.0.split_at({}).1.split_at({}).0", offset, len)
+        }
+    }
+
+    /// Writes the pack into `OUT_DIR`, as a pack file if it's external, and
+    /// generates its `static`.
+    ///
+    /// Without packing, generates the `static` of every distinct data.
+    pub(crate) fn generate(&self) -> String {
+        if let Some(ref statics) = self.statics {
+            return generate_statics(statics);
+        }
+
+        let path = path_to_string(write_out_dir(&self.data));
+        let len = self.data.len();
+        let alignment = Literal::usize_unsuffixed(self.alignment);
+        let embedded = quote! {
+            #[repr(C, align(#alignment))]
+            struct IncluderPack([u8; #len]);
+        };
+
+        let tokens = match self.file {
+            None => quote! {
+                #embedded
+
+                static INCLUDER_PACK: IncluderPack = IncluderPack(*include_bytes!(#path));
+            },
+            Some(PackFileOptions {
+                name,
+                fallback,
+                mapped,
+                verified,
+            }) => {
+                let digest = Sha256::digest(&self.data);
+                let build_path = path_to_string(self.write_file(name, &digest));
+
+                let digest = digest.iter();
+                let fallback = if fallback {
+                    quote! {{
+                        #embedded
+
+                        static DATA: IncluderPack = IncluderPack(*include_bytes!(#path));
+                        Some(&DATA.0)
+                    }}
+                } else {
+                    quote! {None}
+                };
+                quote! {
+                    static INCLUDER_PACK: ::includer::PackFile =
+                        ::includer::PackFile::new(#name, #build_path, [#(#digest),*], #len, #fallback)
+                            .mapped(#mapped)
+                            .verified(#verified);
+                }
+            }
+        };
+
+        format!("{}\n", tokens)
+    }
+
+    /// Writes the pack file into `OUT_DIR`, returning its path.
+    fn write_file(&self, name: &str, digest: &[u8]) -> PathBuf {
+        let out_dir = env::var_os("OUT_DIR").expect("OUT_DIR must be set to write a pack file");
+
+        let offset = self.alignment.max(64);
+        let mut file = b"INCLPACK".to_vec();
+        file.extend(&PACK_VERSION.to_le_bytes());
+        file.extend(&(offset as u32).to_le_bytes());
+        file.extend(&(self.data.len() as u64).to_le_bytes());
+        file.extend(digest);
+        file.resize(offset, 0);
+        file.extend(&self.data);
+
+        let path = Path::new(&out_dir).join(name);
+        if fs::read(&path).ok().as_ref() != Some(&file) {
+            fs::write(&path, file)
+                .unwrap_or_else(|_| panic!("Unable to write `{}`", path.display()));
+        }
+        path
+    }
+}
+
+/// Generates a `static` for every distinct data that isn't packed, wrapped in
+/// an `IncluderAlign{alignment}` struct if it's aligned.
+fn generate_statics(statics: &[(PathBuf, usize, usize)]) -> String {
+    let mut items = Vec::new();
+    let mut wrappers = HashSet::new();
+    for (k, (path, len, alignment)) in statics.iter().enumerate() {
+        let ident = Ident::new(&format!("INCLUDER_DATA_{}", k), Span::call_site());
+        let path = path_to_string(path);
+        if *alignment == 1 {
+            items.push(quote! {
+                static #ident: [u8; #len] = *include_bytes!(#path);
+            });
+            continue;
+        }
+
+        let wrapper = Ident::new(&format!("IncluderAlign{}", alignment), Span::call_site());
+        if wrappers.insert(alignment) {
+            let alignment = Literal::usize_unsuffixed(*alignment);
+            items.push(quote! {
+                #[repr(C, align(#alignment))]
+                struct #wrapper<T>(T);
+            });
+        }
+        items.push(quote! {
+            static #ident: #wrapper<[u8; #len]> = #wrapper(*include_bytes!(#path));
+        });
+    }
+
+    if items.is_empty() {
+        return String::new();
+    }
+
+    format!("{}\n", quote! {#(#items)*})
+}
+
+/// Applies every transform whose rule matches the file, in the order that
+/// they were added.
+///
+/// Returns `None` if no transform matches.
+///
+/// # Panics
+///
+/// Panics if the file can't be read or a transform fails.
+pub fn transform(path: &Path, transforms: &[(FilterRule, Box<dyn Transform>)]) -> Option<Vec<u8>> {
+    let mut matching = transforms.iter().filter(|(rule, _)| rule.matches(path));
+    let first = matching.next()?;
+
+    let data = fs::read(path).unwrap_or_else(|_| panic!("Unable to read `{}`", path.display()));
+    let result = iter::once(first)
+        .chain(matching)
+        .try_fold(data, |data, (_, t)| t.transform(path, data));
+    match result {
+        Ok(data) => Some(data),
+        Err(e) => panic!("Unable to transform `{}`: {}", path.display(), e),
+    }
+}
+
+/// Writes generated data into `$OUT_DIR/includer` and returns its path.
+///
+/// The file is named after the hash of the data, so that pipelines can embed
+/// processed assets with `include_bytes!` and identical data is only written
+/// once.
+///
+/// # Panics
+///
+/// Panics if `OUT_DIR` isn't set, which is the case outside of a build script.
+pub fn write_out_dir(data: &[u8]) -> PathBuf {
+    let out_dir = env::var_os("OUT_DIR").expect("OUT_DIR must be set to write generated assets");
+    let dir = Path::new(&out_dir).join("includer");
+    fs::create_dir_all(&dir).expect("Unable to create generated assets directory");
+
+    let path = dir.join(format!("{:x}", Sha256::digest(data)));
+    if !path.exists() {
+        fs::write(&path, data).expect("Unable to write generated asset");
+    }
+
+    path
+}
+
+/// Returns the cached output of `process` for the data, only processing it on
+/// a cache miss.
+///
+/// Outputs are cached in `$OUT_DIR/includer/cache`, keyed by the name of the
+/// processing step and the hash of the data, so unchanged files aren't
+/// processed again by incremental builds.  Nothing is cached outside of a
+/// build script, where `OUT_DIR` isn't set.
+///
+/// # Panics
+///
+/// Panics if the cache can't be written.
+pub fn cached<F>(name: &str, data: Vec<u8>, process: F) -> Result<Vec<u8>, String>
+where
+    F: FnOnce(Vec<u8>) -> Result<Vec<u8>, String>,
+{
+    let out_dir = match env::var_os("OUT_DIR") {
+        Some(out_dir) => out_dir,
+        None => return process(data),
+    };
+
+    let dir = Path::new(&out_dir).join("includer").join("cache");
+    let path = dir.join(format!("{}-{:x}", name, Sha256::digest(&data)));
+    if let Ok(cached) = fs::read(&path) {
+        return Ok(cached);
+    }
+
+    let processed = process(data)?;
+    fs::create_dir_all(&dir).expect("Unable to create the cache directory");
+    fs::write(&path, &processed).expect("Unable to write to the cache");
+    Ok(processed)
+}
+
 pub(crate) fn path_to_string<P: AsRef<Path>>(path: P) -> String {
     path.as_ref()
         .to_str()
@@ -195,3 +1059,255 @@ pub(crate) fn path_to_string<P: AsRef<Path>>(path: P) -> String {
 pub fn watch_path<P: AsRef<Path>>(p: P) {
     println!("cargo:rerun-if-changed={}", p.as_ref().to_str().unwrap());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(uri: &str, path: &str) -> Entry {
+        Entry {
+            uri: uri.to_string(),
+            path: PathBuf::from(path),
+            logical_uri: None,
+        }
+    }
+
+    fn mounts() -> Vec<Vec<Entry>> {
+        vec![
+            vec![
+                entry("/logo.svg", "dist/logo.svg"),
+                entry("/app.js", "dist/app.js"),
+            ],
+            vec![entry("/logo.svg", "branding/logo.svg")],
+        ]
+    }
+
+    /// Token streams as strings, to compare them regardless of spacing.
+    fn tokens(streams: &[TokenStream]) -> Vec<String> {
+        streams.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn identical_contents() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("vendor")).unwrap();
+        fs::write(dir.join("font.woff2"), "font").unwrap();
+        fs::write(dir.join("vendor/font.woff2"), "font").unwrap();
+        fs::write(dir.join("logo.svg"), "logo").unwrap();
+
+        let paths = [
+            dir.join("font.woff2"),
+            dir.join("logo.svg"),
+            dir.join("vendor/font.woff2"),
+        ];
+        let files = paths.iter().map(|p| (p.as_path(), fs::read(p).unwrap()));
+        let (unique, indices) = unique_contents(files);
+        assert_eq!(
+            unique,
+            vec![(paths[0].as_path(), 4), (paths[1].as_path(), 4)]
+        );
+        assert_eq!(indices, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn unpacked_statics() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.bin"), "abcd").unwrap();
+        fs::write(dir.join("b.bin"), "abcd").unwrap();
+
+        // identical data is shared, unless it needs a larger alignment
+        let (a, b) = (dir.join("a.bin"), dir.join("b.bin"));
+        let mut pack = Pack::unpacked(&[]);
+        assert_eq!(pack.add(&a, &a), (0, 4));
+        assert_eq!(pack.add(&b, &b), (0, 4));
+        assert_eq!(pack.add_aligned(&b, &b, 8), (1, 4));
+        assert_eq!(pack.add_aligned(&a, &a, 4), (1, 4));
+        assert_eq!(pack.slice(0, 4), "&INCLUDER_DATA_0");
+        assert_eq!(pack.slice(1, 4), "&INCLUDER_DATA_1.0");
+
+        let (a, b) = (path_to_string(&a), path_to_string(&b));
+        let expected = quote! {
+            static INCLUDER_DATA_0: [u8; 4usize] = *include_bytes!(#a);
+            #[repr(C, align(8))]
+            struct IncluderAlign8<T>(T);
+            static INCLUDER_DATA_1: IncluderAlign8<[u8; 4usize]> =
+                IncluderAlign8(*include_bytes!(#b));
+        };
+        assert_eq!(pack.generate(), format!("{}\n", expected));
+    }
+
+    #[test]
+    fn pack_alignment() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.txt"), "abc").unwrap();
+        fs::write(dir.join("b.bin"), "abc").unwrap();
+        fs::write(dir.join("c.txt"), "abc").unwrap();
+        fs::write(dir.join("d.txt"), "xyz").unwrap();
+
+        let alignments = [(FilterRule::extension("bin"), 16)];
+        let mut pack = Pack::new(&alignments);
+        assert_eq!(pack.add(&dir.join("a.txt"), &dir.join("a.txt")), (0, 3));
+        assert_eq!(pack.add(&dir.join("b.bin"), &dir.join("b.bin")), (0, 3));
+        fs::write(dir.join("b.bin"), "defg").unwrap();
+        assert_eq!(pack.add(&dir.join("b.bin"), &dir.join("b.bin")), (16, 4));
+        assert_eq!(pack.add(&dir.join("c.txt"), &dir.join("c.txt")), (0, 3));
+        assert_eq!(pack.data.len(), 20);
+        assert_eq!(
+            pack.add_aligned(&dir.join("d.txt"), &dir.join("d.txt"), 64),
+            (64, 3)
+        );
+        assert_eq!(pack.alignment, 64);
+    }
+
+    #[test]
+    fn aligned_statics() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.bin"), "abcd").unwrap();
+        fs::write(dir.join("b.bin"), "abcd").unwrap();
+        fs::write(dir.join("c.txt"), "efg").unwrap();
+
+        let (a, b, c) = (dir.join("a.bin"), dir.join("b.bin"), dir.join("c.txt"));
+        let aligned = |path, alignment| EmbedFile {
+            alignment,
+            ..EmbedFile::new(path, path)
+        };
+        let files = [aligned(&a, 8), aligned(&b, 16), aligned(&c, 1)];
+        let embedded = embed(&files, None);
+        assert_eq!(embedded.indices, vec![0, 0, 1]);
+
+        let (a, c) = (path_to_string(&a), path_to_string(&c));
+        let items = [
+            quote! {
+                #[repr(C, align(16))]
+                struct Align16<T>(T);
+            },
+            quote! {
+                static DATA_0: Align16<[u8; 4usize]> = Align16(*include_bytes!(#a));
+            },
+            quote! {
+                static DATA_1: [u8; 3usize] = *include_bytes!(#c);
+            },
+        ];
+        assert_eq!(tokens(&embedded.items), tokens(&items));
+        assert_eq!(
+            tokens(&embedded.refs),
+            tokens(&[quote! {&DATA_0.0}, quote! {&DATA_1}])
+        );
+    }
+
+    #[test]
+    fn text_statics() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        fs::write(dir.join("a.sql"), "select 1;").unwrap();
+        fs::write(dir.join("b.sql"), "select 2;").unwrap();
+        fs::write(dir.join("c.bin"), [0xff, 0xfe]).unwrap();
+
+        let (a, b, c) = (dir.join("a.sql"), dir.join("b.sql"), dir.join("c.bin"));
+        let text = |path, alignment| EmbedFile {
+            alignment,
+            text: true,
+            ..EmbedFile::new(path, path)
+        };
+        let embedded = embed(&[text(&a, 1), text(&b, 8)], None);
+        let (a, b) = (path_to_string(&a), path_to_string(&b));
+        let items = [
+            quote! {
+                static DATA_0: &str = include_str!(#a);
+            },
+            quote! {
+                #[repr(C, align(8))]
+                struct Align8<T>(T);
+            },
+            quote! {
+                static DATA_1: Align8<[u8; 9usize]> = Align8(*include_bytes!(#b));
+            },
+        ];
+        assert_eq!(tokens(&embedded.items), tokens(&items));
+        assert_eq!(
+            tokens(&embedded.refs),
+            tokens(&[quote! {DATA_0.as_bytes()}, quote! {&DATA_1.0}])
+        );
+
+        let texts: Vec<_> = embedded.texts.into_iter().map(Option::unwrap).collect();
+        let validated = quote! {
+            match ::std::str::from_utf8(&DATA_1.0) {
+                Ok(text) => text,
+                Err(_) => panic!("Embedded text is not valid UTF-8"),
+            }
+        };
+        assert_eq!(tokens(&texts), tokens(&[quote! {DATA_0}, validated]));
+
+        let result = std::panic::catch_unwind(|| embed(&[text(&c, 1)], None));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn later_mounts_overlay() {
+        let merged = merge_mounts(mounts(), CollisionPolicy::LastWins);
+        assert_eq!(
+            merged,
+            vec![
+                entry("/logo.svg", "branding/logo.svg"),
+                entry("/app.js", "dist/app.js")
+            ]
+        );
+    }
+
+    #[test]
+    fn earlier_mounts_win() {
+        let merged = merge_mounts(mounts(), CollisionPolicy::FirstWins);
+        assert_eq!(merged, mounts().remove(0));
+    }
+
+    #[test]
+    fn aliases_share_paths() {
+        let mut entries = vec![entry("/static/favicon.ico", "dist/favicon.ico")];
+        add_aliases(
+            &mut entries,
+            &[("/favicon.ico".into(), "/static/favicon.ico".into())],
+        );
+        assert_eq!(entries[1], entry("/favicon.ico", "dist/favicon.ico"));
+
+        let (paths, indices) = unique_paths(&entries);
+        assert_eq!(paths, vec![Path::new("dist/favicon.ico")]);
+        assert_eq!(indices, vec![0, 0]);
+    }
+
+    #[test]
+    fn same_mount_duplicates_are_kept() {
+        let same = vec![vec![
+            entry("/docs", "dist/docs"),
+            entry("/docs", "dist/docs/index.html"),
+        ]];
+        assert_eq!(merge_mounts(same, CollisionPolicy::Error).len(), 2);
+    }
+
+    #[test]
+    fn transforms_chain() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let path = dir.join("config.js");
+        fs::write(&path, "v__VERSION__").unwrap();
+
+        let version = |_: &Path, data: Vec<u8>| -> Result<Vec<u8>, String> {
+            Ok(String::from_utf8(data)
+                .unwrap()
+                .replace("__VERSION__", "1")
+                .into_bytes())
+        };
+        let upper =
+            |_: &Path, data: Vec<u8>| -> Result<Vec<u8>, String> { Ok(data.to_ascii_uppercase()) };
+        let transforms: Vec<(FilterRule, Box<dyn Transform>)> = vec![
+            (FilterRule::extension("js"), Box::new(version)),
+            (FilterRule::extension("css"), Box::new(upper)),
+            (FilterRule::extension("js"), Box::new(upper)),
+        ];
+        assert_eq!(transform(&path, &transforms), Some(b"V1".to_vec()));
+        assert_eq!(transform(&path, &transforms[1..2]), None);
+    }
+}